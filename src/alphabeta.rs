@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, Duration};
+use log::*;
+use crate::api::{ApiDirection, ApiGameState};
+use crate::brain::{Score, death_score, with_kills, without_kills, prune_snake_moves};
+use crate::game::Board;
+use crate::heuristic::Weights;
+use crate::util::cartesian_product;
+
+//only check the clock once per this many boards
+const CLOCK_INTERVAL: usize = 64;
+
+//paranoid search: we and our allies pick a joint move, then the enemies pick the joint reply that's worst for us
+pub struct AlphaBeta {
+    //none if only the depth limit stops the search
+    deadline: Option<SystemTime>,
+    n_considered: usize,
+    //whether any leaf was scored because of the depth limit rather than the game ending
+    depth_limited: bool,
+    //index of the best joint move found at each position, tried first next time. enemy replies are also keyed by our move
+    best_moves: HashMap<(u64, Option<usize>), usize>,
+    weights: Weights,
+}
+
+impl AlphaBeta {
+    pub fn new(budget: Duration, weights: Weights) -> AlphaBeta {
+        AlphaBeta::with_deadline(Some(SystemTime::now() + budget), weights)
+    }
+
+    //never runs out of time, so the same board and depth always give the same result
+    pub fn unlimited(weights: Weights) -> AlphaBeta {
+        AlphaBeta::with_deadline(None, weights)
+    }
+
+    fn with_deadline(deadline: Option<SystemTime>, weights: Weights) -> AlphaBeta {
+        AlphaBeta {
+            deadline,
+            n_considered: 0,
+            depth_limited: false,
+            best_moves: HashMap::new(),
+            weights,
+        }
+    }
+
+    pub fn n_considered(&self) -> usize {
+        self.n_considered
+    }
+
+    //searches one turn deeper each time until the budget elapses or `max_depth` is done, keeping the deepest complete result
+    pub fn run(&mut self, board: &Board, max_depth: usize) -> (ApiDirection, Score, usize) {
+        let mut board = board.clone();
+        let mut result = (board.you().get_default_move(), 0.0, 0);
+        for depth in 1..=max_depth {
+            self.depth_limited = false;
+            match self.max_value(&mut board, depth, Score::NEG_INFINITY, Score::INFINITY) {
+                Some((score, dir)) => {
+                    result = (dir, score, depth);
+                    if !self.depth_limited {
+                        break;
+                    }
+                },
+                None => break,
+            }
+        }
+        result
+    }
+
+    //joint move indices with the best from last time first
+    fn ordered(&self, n_moves: usize, key: (u64, Option<usize>)) -> Vec<usize> {
+        let mut order = (0..n_moves).collect::<Vec<_>>();
+        if let Some(&best) = self.best_moves.get(&key) {
+            if best < n_moves {
+                order.swap(0, best);
+            }
+        }
+        order
+    }
+
+    //our side's best score and move, or None if out of time
+    fn max_value(&mut self, board: &mut Board, depth: usize, mut alpha: Score, beta: Score) -> Option<(Score, ApiDirection)> {
+        let mut snake_moves = board.enumerate_snake_moves(&board.bits());
+        prune_snake_moves(board, &mut snake_moves);
+        let (ours, theirs): (Vec<usize>, Vec<usize>) = (0..board.snakes.len()).partition(|&i| i == 0 || board.allied(0, i));
+        let our_moves = cartesian_product(&ours.iter().map(|&i| snake_moves[i].clone()).collect::<Vec<_>>());
+        let their_moves = cartesian_product(&theirs.iter().map(|&i| snake_moves[i].clone()).collect::<Vec<_>>());
+        let allied = (0..board.snakes.len()).map(|i| board.allied(0, i)).collect::<Vec<_>>();
+
+        let hash = board.zobrist_hash();
+        let mut moves = vec![ApiDirection::Up; board.snakes.len()];
+        let mut best: Option<(Score, usize)> = None;
+        for our_index in self.ordered(our_moves.len(), (hash, None)) {
+            for (&i, &dir) in ours.iter().zip(our_moves[our_index].iter()) {
+                moves[i] = dir;
+            }
+            let mut worst: Option<(Score, usize)> = None;
+            let mut their_beta = beta;
+            for their_index in self.ordered(their_moves.len(), (hash, Some(our_index))) {
+                if self.n_considered.is_multiple_of(CLOCK_INTERVAL) && self.deadline.map(|deadline| SystemTime::now() >= deadline).unwrap_or(false) {
+                    return None;
+                }
+                for (&i, &dir) in theirs.iter().zip(their_moves[their_index].iter()) {
+                    moves[i] = dir;
+                }
+                let value = self.min_child(board, &moves, &allied, depth, alpha, their_beta)?;
+                if worst.map(|(worst, _)| value < worst).unwrap_or(true) {
+                    worst = Some((value, their_index));
+                }
+                their_beta = their_beta.min(value);
+                if alpha >= their_beta {
+                    break;
+                }
+            }
+            let (value, their_index) = worst.unwrap();
+            self.best_moves.insert((hash, Some(our_index)), their_index);
+
+            if best.map(|(best, _)| value > best).unwrap_or(true) {
+                best = Some((value, our_index));
+            }
+            alpha = alpha.max(value);
+            if alpha >= beta {
+                break;
+            }
+        }
+        let (value, our_index) = best.unwrap();
+        self.best_moves.insert((hash, None), our_index);
+        Some((value, our_moves[our_index][0]))
+    }
+
+    //applies everyone's moves and scores the result, searching deeper if there's depth left
+    fn min_child(&mut self, board: &mut Board, moves: &[ApiDirection], allied: &[bool], depth: usize, alpha: Score, beta: Score) -> Option<Score> {
+        let undo = board.apply(None, moves);
+        self.n_considered += 1;
+        let value = if let Some(elimination) = undo.eliminations.get(&0) {
+            Some(death_score(elimination.cause))
+        } else {
+            let n_kills = undo.eliminations.iter()
+                .filter(|(&snake_index, elimination)| elimination.by == Some(0) && !allied[snake_index])
+                .count();
+            let value = if board.game_over() {
+                Some(self.weights.score(board, 0))
+            } else if depth <= 1 {
+                self.depth_limited = true;
+                Some(self.weights.score(board, 0))
+            } else {
+                //the bonus is applied on the way back up, so the child's window has to be taken back through it
+                self.max_value(board, depth - 1, without_kills(alpha, n_kills), without_kills(beta, n_kills)).map(|(value, _)| value)
+            };
+            value.map(|value| with_kills(value, n_kills))
+        };
+        board.undo(undo);
+        value
+    }
+}
+
+pub fn get_decision(game_state: &ApiGameState, budget: Duration, weights: Weights) -> ApiDirection {
+    let mut search = AlphaBeta::new(budget, weights);
+    let (decision, score, depth) = search.run(&Board::from_api(game_state), usize::MAX);
+    info!("Budget elapsed: n_considered={}, depth={}, score={}", search.n_considered(), depth, score);
+    decision
+}
+
+//searches exactly `depth` turns ahead however long it takes, for when results must be reproducible
+pub fn get_decision_at_depth(board: &Board, depth: usize, weights: Weights) -> ApiDirection {
+    AlphaBeta::unlimited(weights).run(board, depth).0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::ApiDirection::*;
+    use crate::brain::prune_snake_moves;
+    use crate::game::{Coord, GameMode};
+
+    macro_rules! decide {
+        ($s:expr) => {
+            get_decision(&ApiGameState::parse_basic($s), Duration::from_millis(200), Weights::default())
+        };
+    }
+
+    //plain paranoid minimax, to check pruning doesn't change the result
+    fn minimax(board: &Board, depth: usize) -> Score {
+        let mut snake_moves = board.enumerate_snake_moves(&board.bits());
+        prune_snake_moves(board, &mut snake_moves);
+        let ours = (0..board.snakes.len()).filter(|&i| i == 0 || board.allied(0, i)).collect::<Vec<_>>();
+        let mut by_our_moves: HashMap<Vec<ApiDirection>, Score> = HashMap::new();
+        for moves in cartesian_product(&snake_moves) {
+            let mut next_board = board.clone();
+            let eliminations = next_board.advance(None, &moves);
+            let value = if let Some(elimination) = eliminations.get(&0) {
+                death_score(elimination.cause)
+            } else {
+                let n_kills = eliminations.iter()
+                    .filter(|(&snake_index, elimination)| elimination.by == Some(0) && !board.allied(0, snake_index))
+                    .count();
+                let value = if next_board.game_over() || depth <= 1 {
+                    Weights::default().score(&next_board, 0)
+                } else {
+                    minimax(&next_board, depth - 1)
+                };
+                with_kills(value, n_kills)
+            };
+            let worst = by_our_moves.entry(ours.iter().map(|&i| moves[i]).collect()).or_insert(value);
+            *worst = worst.min(value);
+        }
+        by_our_moves.values().copied().fold(Score::NEG_INFINITY, Score::max)
+    }
+
+    #[test]
+    fn test_matches_minimax() {
+        let board = Board::from_api(&ApiGameState::parse_basic("
+        |  |  |  |  |  |  |
+        |  |Y0|  |  |()|  |
+        |  |Y1|  |A1|A0|  |
+        |  |Y2|  |A2|  |  |
+        |  |  |  |  |  |  |
+        "));
+        //A has to dodge us or die, so some lines start with a kill. those have to be searched with the bounds
+        //the kill bonus maps to
+        let kill = Board::from_api(&ApiGameState::parse_basic("
+        |  |  |  |Y0|Y1|
+        |  |  |  |Y3|Y2|
+        |  |B0|A4|A3|A0|
+        |B2|B1|  |A2|A1|
+        |  |  |  |  |  |
+        +Y95
+        +A94
+        +B91
+        "));
+        //with A on our side, only B's elimination would count as a kill
+        let mut squad = kill.clone();
+        squad.set_mode(GameMode::Squad);
+        let ally = squad.snakes.iter().position(|snake| snake.head() == Coord::new(4, 2)).unwrap();
+        squad.snakes[0].squad = Some(1);
+        squad.snakes[ally].squad = Some(1);
+        squad.snakes[3 - ally].squad = Some(2);
+        for (i, board) in [board, kill, squad].iter().enumerate() {
+            for depth in 1..=3 {
+                let mut search = AlphaBeta::new(Duration::from_secs(60), Weights::default());
+                let mut searched = board.clone();
+                let (score, _) = search.max_value(&mut searched, depth, Score::NEG_INFINITY, Score::INFINITY).unwrap();
+                assert_eq!(score, minimax(board, depth), "board {} depth {}", i, depth);
+                assert_eq!(&searched, board);
+            }
+        }
+    }
+
+    #[test]
+    fn test_exhausted() {
+        //every line ends within a couple turns, so deepening stops before the budget
+        let mut search = AlphaBeta::new(Duration::from_secs(60), Weights::default());
+        let (dir, _, depth) = search.run(&Board::from_api(&ApiGameState::parse_basic("
+        |Y0|Y1|
+        |  |Y2|
+        ")), usize::MAX);
+        assert_eq!(dir, Down);
+        assert!(depth <= 3);
+    }
+
+    #[test]
+    fn test_facing_self() {
+        assert_eq!(Left, decide!("
+        |  |  |  |  |  |
+        |Y8|Y7|Y6|Y5|  |
+        |  |Y0|  |Y4|  |
+        |  |Y1|Y2|Y3|  |
+        |  |  |  |  |  |
+        "));
+    }
+
+    #[test]
+    fn test_lookahead_basic() {
+        assert_eq!(Right, decide!("
+        |  |A1|A2|A3|
+        |  |A0|Y0|A4|
+        |  |  |Y1|  |
+        "));
+    }
+
+    #[test]
+    fn test_enemy_already_trapped() {
+        assert_eq!(Right, decide!("
+        |  |Y0|  |  |  |
+        |  |Y1|  |  |  |
+        |A0|Y2|  |  |  |
+        |A1|Y3|Y4|  |  |
+        |A2|A3|A4|  |  |
+        "));
+    }
+}
+
@@ -1,10 +1,14 @@
 use std::cmp::max;
 use std::collections::HashMap;
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Deserialize, Deserializer};
+use serde_json::Value;
 
 pub type ApiGameId = String;
 pub type ApiSnakeId = String;
 
+pub const API_VERSION: &str = "1";
+
+//2020 API: returned from /start
 #[derive(Deserialize, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ApiSnakeConfig {
@@ -13,9 +17,105 @@ pub struct ApiSnakeConfig {
     pub tail_type: String,
 }
 
-#[derive(Deserialize, Serialize)]
+//v1 API: returned from GET /
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ApiSnakeInfo {
+    pub apiversion: String,
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub color: String,
+    #[serde(default)]
+    pub head: String,
+    #[serde(default)]
+    pub tail: String,
+    #[serde(default)]
+    pub version: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ApiRoyaleSettings {
+    pub shrink_every_n_turns: u32,
+}
+
+impl Default for ApiRoyaleSettings {
+    fn default() -> Self {
+        ApiRoyaleSettings {
+            shrink_every_n_turns: 25,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ApiSquadSettings {
+    pub allow_body_collisions: bool,
+    pub shared_elimination: bool,
+    pub shared_health: bool,
+    pub shared_length: bool,
+}
+
+impl Default for ApiSquadSettings {
+    fn default() -> Self {
+        ApiSquadSettings {
+            allow_body_collisions: true,
+            shared_elimination: true,
+            shared_health: true,
+            shared_length: true,
+        }
+    }
+}
+
+//fields missing from the request take the standard ruleset's values
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ApiRulesetSettings {
+    pub food_spawn_chance: u32,
+    pub minimum_food: u32,
+    pub hazard_damage_per_turn: u32,
+    pub royale: ApiRoyaleSettings,
+    pub squad: ApiSquadSettings,
+}
+
+impl Default for ApiRulesetSettings {
+    fn default() -> Self {
+        ApiRulesetSettings {
+            food_spawn_chance: 15,
+            minimum_food: 1,
+            hazard_damage_per_turn: 14,
+            royale: ApiRoyaleSettings::default(),
+            squad: ApiSquadSettings::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct ApiRuleset {
+    pub name: String,
+    pub version: String,
+    pub settings: ApiRulesetSettings,
+}
+
+impl Default for ApiRuleset {
+    fn default() -> Self {
+        ApiRuleset {
+            name: String::from("standard"),
+            version: String::new(),
+            settings: ApiRulesetSettings::default(),
+        }
+    }
+}
+
+//`ruleset` and `timeout` are absent in the 2020 API
+#[derive(Deserialize, Serialize, Clone, Default)]
 pub struct ApiGame {
     pub id: ApiGameId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ruleset: Option<ApiRuleset>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u32>,
 }
 
 #[derive(Deserialize, Serialize, Copy, Clone, PartialEq, Debug)]
@@ -24,23 +124,52 @@ pub struct ApiCoords {
     pub y: u32,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone, Default)]
 pub struct ApiBoard {
     pub height: u32,
     pub width: u32,
     pub food: Vec<ApiCoords>,
     pub snakes: Vec<ApiSnake>,
+    #[serde(default)]
+    pub hazards: Vec<ApiCoords>,
 }
 
-#[derive(Deserialize, Serialize, Clone)]
+//everything after `body` was added in the v1 API
+#[derive(Deserialize, Serialize, Clone, Default)]
 pub struct ApiSnake {
     pub id: ApiSnakeId,
     pub name: String,
     pub health: u32,
     pub body: Vec<ApiCoords>,
+    #[serde(default, deserialize_with = "deserialize_latency", skip_serializing_if = "Option::is_none")]
+    pub latency: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub head: Option<ApiCoords>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shout: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub squad: Option<String>,
 }
 
-#[derive(Deserialize, Serialize)]
+//engines disagree on whether latency is a string or a number, so accept both
+fn deserialize_latency<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::String(s) => Some(s),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    })
+}
+
+impl ApiSnake {
+    //the last round trip time the engine measured for this snake, if it reported one
+    pub fn latency_ms(&self) -> Option<u64> {
+        self.latency.as_ref().and_then(|latency| latency.parse().ok())
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
 pub struct ApiGameState {
     pub game: ApiGame,
     pub turn: u32,
@@ -75,6 +204,8 @@ pub const ALL_DIRS: [ApiDirection; 4] = [ApiDirection::Down, ApiDirection::Left,
 pub struct ApiMove {
     #[serde(rename = "move")]
     pub decision: ApiDirection,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shout: Option<String>,
 }
 
 impl ApiGameState {
@@ -82,8 +213,10 @@ impl ApiGameState {
         let mut height = 0;
         let mut width = 0;
         let mut food = Vec::new();
+        let mut hazards = Vec::new();
         let mut snakes_coords: HashMap<String, Vec<ApiCoords>> = HashMap::new();
         let mut snake_health: HashMap<String, u32> = HashMap::new();
+        let mut snake_squads: HashMap<String, String> = HashMap::new();
         let mut you_coords = Vec::new();
 
         for row in s.lines().map(str::trim) {
@@ -92,7 +225,11 @@ impl ApiGameState {
                 width = std::cmp::max(width, cols.len());
                 for (x, &col) in cols.iter().enumerate() {
                     let coord = ApiCoords {x: x as u32, y: height as u32};
-                    match col.trim() {
+                    //a `~` anywhere in the cell marks it as a hazard
+                    if col.contains('~') {
+                        hazards.push(coord);
+                    }
+                    match col.trim().trim_matches('~') {
                         "" => {},
                         "()" => {
                             food.push(coord);
@@ -123,11 +260,15 @@ impl ApiGameState {
                 let snake_name: String = row.chars().skip(1).take_while(|&c| c.is_alphabetic()).collect();
                 let health: u32 = row.chars().skip(1).skip_while(|&c| c.is_alphabetic()).collect::<String>().parse().unwrap();
                 snake_health.insert(snake_name, health);
+            } else if row.starts_with('=') {
+                let snake_name: String = row.chars().skip(1).take_while(|&c| c.is_alphabetic()).collect();
+                let squad: String = row.chars().skip(1).skip_while(|&c| c.is_alphabetic()).collect();
+                snake_squads.insert(snake_name, squad);
             }
         }
 
         ApiGameState {
-            game: ApiGame {id: ApiGameId::from("123")},
+            game: ApiGame {id: ApiGameId::from("123"), ..Default::default()},
             turn: 0,
             board: ApiBoard {
                 height: height as u32,
@@ -137,14 +278,19 @@ impl ApiGameState {
                     id: format!("id_{}", name),
                     name: name.clone(),
                     health: snake_health.get(name).copied().unwrap_or(100),
-                    body: body.clone()
+                    body: body.clone(),
+                    squad: snake_squads.get(name).cloned(),
+                    ..Default::default()
                 }).collect(),
+                hazards,
             },
             you: ApiSnake {
                 id: String::from("id_Y"),
                 name: String::from("Y"),
                 health: snake_health.get("Y").copied().unwrap_or(100),
                 body: you_coords,
+                squad: snake_squads.get("Y").cloned(),
+                ..Default::default()
             }
         }
     }
@@ -166,4 +312,84 @@ pub mod tests {
 
         assert_eq!(game.you.body.len(), 9);
     }
+
+    #[test]
+    fn test_parse_hazards() {
+        let game = ApiGameState::parse_basic("
+        |~ |  |()~|
+        |  |Y0|~Y1|
+        ");
+        assert_eq!(game.board.hazards, vec![ApiCoords {x: 0, y: 0}, ApiCoords {x: 2, y: 0}, ApiCoords {x: 2, y: 1}]);
+        assert_eq!(game.board.food.len(), 1);
+        assert_eq!(game.you.body.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_v1() {
+        let game: ApiGameState = serde_json::from_str(r#"
+        {
+          "game": {
+            "id": "game-00fe20da-94ad-11ea-bb37",
+            "ruleset": {
+              "name": "royale",
+              "version": "v1.2.3",
+              "settings": {
+                "foodSpawnChance": 25,
+                "minimumFood": 2,
+                "hazardDamagePerTurn": 14,
+                "royale": {
+                  "shrinkEveryNTurns": 10
+                }
+              }
+            },
+            "timeout": 500
+          },
+          "turn": 14,
+          "board": {
+            "height": 11,
+            "width": 11,
+            "food": [{"x": 5, "y": 5}],
+            "hazards": [{"x": 0, "y": 0}, {"x": 0, "y": 1}],
+            "snakes": [
+              {
+                "id": "snake-508e96ac-94ad-11ea-bb37",
+                "name": "My Snake",
+                "health": 54,
+                "body": [{"x": 0, "y": 0}, {"x": 1, "y": 0}, {"x": 2, "y": 0}],
+                "latency": "111",
+                "head": {"x": 0, "y": 0},
+                "length": 3,
+                "shout": "why are we shouting??",
+                "squad": "1"
+              }
+            ]
+          },
+          "you": {
+            "id": "snake-508e96ac-94ad-11ea-bb37",
+            "name": "My Snake",
+            "health": 54,
+            "body": [{"x": 0, "y": 0}, {"x": 1, "y": 0}, {"x": 2, "y": 0}],
+            "latency": 111,
+            "head": {"x": 0, "y": 0},
+            "length": 3,
+            "shout": "why are we shouting??",
+            "squad": "1"
+          }
+        }
+        "#).unwrap();
+
+        let ruleset = game.game.ruleset.as_ref().unwrap();
+        assert_eq!(ruleset.name, "royale");
+        assert_eq!(ruleset.settings.food_spawn_chance, 25);
+        assert_eq!(ruleset.settings.hazard_damage_per_turn, 14);
+        assert_eq!(ruleset.settings.royale.shrink_every_n_turns, 10);
+        assert_eq!(ruleset.settings.squad, ApiSquadSettings::default());
+        assert_eq!(game.game.timeout, Some(500));
+        assert_eq!(game.board.hazards.len(), 2);
+        assert_eq!(game.board.snakes[0].latency_ms(), Some(111));
+        assert_eq!(game.you.latency_ms(), Some(111));
+        assert_eq!(game.you.head, Some(ApiCoords {x: 0, y: 0}));
+        assert_eq!(game.you.length, Some(3));
+        assert_eq!(game.you.squad.as_deref(), Some("1"));
+    }
 }
@@ -1,8 +1,10 @@
 use std::str;
 use std::time::{SystemTime, Duration};
 use log::*;
-use crate::game::{Path, Coord, Offset, Board};
+use crate::game::{Path, Coord, Offset, Board, BoardBits};
 use crate::brain::get_decision;
+use crate::{alphabeta, mcts};
+use crate::heuristic::Weights;
 use crate::api::{ApiDirection::*, ApiGameState};
 
 macro_rules! timed {
@@ -61,16 +63,34 @@ fn board() {
     ");
     let board = Board::from_api(&game_state);
 
+    timed!("board_bits", 10_000, |_| {
+        BoardBits::new(&board)
+    });
+
+    //the rest share one build, like searches do
+    let bits = board.bits();
     timed!("get_free_moves", 1_000, |_| {
-        board.enumerate_snake_moves()
+        board.enumerate_snake_moves(&bits)
     });
 
     timed!("pathfind", 1_000, |_| {
-        let _path = board.pathfind(board.you().head(), Coord::new(11, 11));
+        let _path = board.pathfind(&bits, board.you().head(), Coord::new(11, 11));
     });
 
     timed!("territories", 10_000, |_| {
-        let _territories = board.get_territories();
+        let _territories = board.get_territories(&bits);
+    });
+
+    let moves = board.enumerate_snake_moves(&bits).iter().map(|dirs| dirs[0]).collect::<Vec<_>>();
+    timed!("advance_clone", 10_000, |_| {
+        let mut next_board = board.clone();
+        next_board.advance(None, &moves)
+    });
+
+    let mut next_board = board.clone();
+    timed!("apply_undo", 10_000, |_| {
+        let undo = next_board.apply(None, &moves);
+        next_board.undo(undo);
     });
 }
 
@@ -94,6 +114,14 @@ fn decision() {
         get_decision(&game_state, Duration::from_millis(250));
     });
 
+    timed!("alphabeta_early_game", 3, |_| {
+        alphabeta::get_decision(&game_state, Duration::from_millis(250), Weights::default());
+    });
+
+    timed!("mcts_early_game", 3, |_| {
+        mcts::get_decision(&game_state, Duration::from_millis(250), Weights::default(), 0);
+    });
+
     let game_state = ApiGameState::parse_basic("
     |   |   |   |   |A17|   |   |   |   |   |() |
     |   |   |   |   |A16|   |   |   |   |   |   |
@@ -112,6 +140,14 @@ fn decision() {
         get_decision(&game_state, Duration::from_millis(250));
     });
 
+    timed!("alphabeta_late_game", 3, |_| {
+        alphabeta::get_decision(&game_state, Duration::from_millis(250), Weights::default());
+    });
+
+    timed!("mcts_late_game", 3, |_| {
+        mcts::get_decision(&game_state, Duration::from_millis(250), Weights::default(), 0);
+    });
+
     //this is a particularly bad case for choosing priority snakes
     let game_state = ApiGameState::parse_basic("
     |  |  |  |  |  |  |  |  |  |  |  |
@@ -0,0 +1,429 @@
+use std::ops::{BitAnd, BitOr, BitXor, Not, BitAndAssign, BitOrAssign};
+use crate::api::{ApiDirection, ALL_DIRS};
+use super::coord::{Coord, UnitAbs};
+use super::board::Board;
+
+const WORDS: usize = 10;
+//enough for the largest official board, 25x25
+pub const MAX_BITBOARD_AREA: UnitAbs = WORDS * 64;
+
+//a set of board spaces, one bit per space in row-major order
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Bitboard([u64; WORDS]);
+
+impl Bitboard {
+    pub const EMPTY: Bitboard = Bitboard([0; WORDS]);
+
+    #[inline]
+    pub fn get(&self, i: usize) -> bool {
+        self.0[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    #[inline]
+    pub fn set(&mut self, i: usize) {
+        self.0[i / 64] |= 1 << (i % 64);
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|&word| word == 0)
+    }
+
+    #[inline]
+    pub fn count(&self) -> UnitAbs {
+        self.0.iter().map(|word| word.count_ones() as UnitAbs).sum()
+    }
+
+    //moves every bit `n` places towards the high end, dropping any which overflow
+    pub fn shl(&self, n: usize) -> Bitboard {
+        let (words, bits) = (n / 64, n % 64);
+        let mut result = Bitboard::EMPTY;
+        for i in (words..WORDS).rev() {
+            let src = i - words;
+            result.0[i] = self.0[src] << bits;
+            if bits > 0 && src > 0 {
+                result.0[i] |= self.0[src - 1] >> (64 - bits);
+            }
+        }
+        result
+    }
+
+    //moves every bit `n` places towards the low end
+    pub fn shr(&self, n: usize) -> Bitboard {
+        let (words, bits) = (n / 64, n % 64);
+        let mut result = Bitboard::EMPTY;
+        for i in 0..WORDS.saturating_sub(words) {
+            let src = i + words;
+            result.0[i] = self.0[src] >> bits;
+            if bits > 0 && src + 1 < WORDS {
+                result.0[i] |= self.0[src + 1] << (64 - bits);
+            }
+        }
+        result
+    }
+
+    #[cfg(test)]
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.0.iter().enumerate().flat_map(|(w, &word)| {
+            let mut word = word;
+            std::iter::from_fn(move || {
+                if word == 0 {
+                    None
+                } else {
+                    let bit = word.trailing_zeros() as usize;
+                    word &= word - 1;
+                    Some(w * 64 + bit)
+                }
+            })
+        })
+    }
+}
+
+macro_rules! bitwise_op {
+    ($trait:ident, $fn:ident, $op:tt) => {
+        impl $trait for Bitboard {
+            type Output = Bitboard;
+
+            #[inline]
+            fn $fn(self, rhs: Bitboard) -> Bitboard {
+                let mut result = self;
+                for (a, b) in result.0.iter_mut().zip(rhs.0.iter()) {
+                    *a $op *b;
+                }
+                result
+            }
+        }
+    };
+}
+
+bitwise_op!(BitAnd, bitand, &=);
+bitwise_op!(BitOr, bitor, |=);
+bitwise_op!(BitXor, bitxor, ^=);
+
+impl BitAndAssign for Bitboard {
+    #[inline]
+    fn bitand_assign(&mut self, rhs: Bitboard) {
+        *self = *self & rhs;
+    }
+}
+
+impl BitOrAssign for Bitboard {
+    #[inline]
+    fn bitor_assign(&mut self, rhs: Bitboard) {
+        *self = *self | rhs;
+    }
+}
+
+impl Not for Bitboard {
+    type Output = Bitboard;
+
+    #[inline]
+    fn not(self) -> Bitboard {
+        let mut result = self;
+        for word in result.0.iter_mut() {
+            *word = !*word;
+        }
+        result
+    }
+}
+
+//packed snapshot of a board for move generation and flood fills. built fresh from a `Board`, so it's
+//always in sync with it, and cheap enough to build once per search node
+#[derive(Clone, Debug)]
+pub struct BoardBits {
+    width: UnitAbs,
+    area: UnitAbs,
+    wraps: bool,
+    //every space on the board
+    all: Bitboard,
+    first_col: Bitboard,
+    last_col: Bitboard,
+    first_row: Bitboard,
+    last_row: Bitboard,
+    //every space covered by a snake
+    pub occupied: Bitboard,
+    //each snake's spaces which will still be covered after the next move
+    pub bodies: Vec<Bitboard>,
+    //spaces still covered by a snake after the number of turns matching their index. the last entry holds for
+    //every later turn too, since it's only the spaces which never vacate
+    covered: Vec<Bitboard>,
+    pub food: Bitboard,
+    pub hazards: Bitboard,
+    heads: Vec<Coord>,
+    //snakes able to pass through each other's bodies, by index
+    passthrough: Vec<Option<u8>>,
+}
+
+impl BoardBits {
+    pub fn new(board: &Board) -> BoardBits {
+        let width = board.width();
+        let area = board.area();
+        assert!(area <= MAX_BITBOARD_AREA, "Boards larger than {} spaces are not supported", MAX_BITBOARD_AREA);
+
+        //built a word or a row at a time, since this runs for every search node
+        let mut all = Bitboard::EMPTY;
+        for (w, word) in all.0.iter_mut().enumerate().take(area.div_ceil(64)) {
+            let bits = (area - w * 64).min(64);
+            *word = if bits == 64 {u64::MAX} else {(1 << bits) - 1};
+        }
+        let mut first_col = Bitboard::EMPTY;
+        for i in (0..area).step_by(width) {
+            first_col.set(i);
+        }
+        let last_col = first_col.shl(width - 1);
+        let first_row = all & !all.shl(width);
+        let last_row = first_row.shl(area - width);
+
+        let mut bits = BoardBits {
+            width,
+            area,
+            wraps: board.wraps(),
+            all,
+            first_col,
+            last_col,
+            first_row,
+            last_row,
+            occupied: Bitboard::EMPTY,
+            bodies: Vec::with_capacity(board.snakes.len()),
+            covered: Vec::new(),
+            food: Bitboard::EMPTY,
+            hazards: Bitboard::EMPTY,
+            heads: board.snakes.iter().map(|snake| snake.head()).collect(),
+            passthrough: Vec::new(),
+        };
+
+        //each space goes in the bucket for the turn its last covering node leaves, with constrictor bodies never
+        //leaving. stacked nodes and overlapping allies then take care of themselves when the buckets are summed
+        let tails_vacate = !board.mode().ruleset().always_grows();
+        let max_size = board.snakes.iter().map(|snake| snake.size()).max().unwrap_or(0);
+        let never = max_size + 1;
+        let mut vacating = vec![Bitboard::EMPTY; never + 1];
+        for snake in board.snakes.iter() {
+            let size = snake.size();
+            let mut body = Bitboard::EMPTY;
+            for (node_index, &node) in snake.body.nodes.iter().enumerate() {
+                let i = match bits.index(node) {
+                    Some(i) => i,
+                    None => continue,
+                };
+                let vacate_turn = if tails_vacate {size - node_index} else {never};
+                if vacate_turn > 1 {
+                    body.set(i);
+                }
+                vacating[vacate_turn].set(i);
+                bits.occupied.set(i);
+            }
+            bits.bodies.push(body);
+        }
+        //a space is still covered after `t` turns if anything in it leaves later than that
+        bits.covered = vec![Bitboard::EMPTY; never];
+        let mut later = vacating[never];
+        for turn in (0..never).rev() {
+            bits.covered[turn] = later;
+            later |= vacating[turn];
+        }
+
+        if board.mode().ruleset().passes_through_allies(board) {
+            bits.passthrough = board.snakes.iter().map(|snake| snake.squad).collect();
+        }
+        for &food in board.food.iter() {
+            if let Some(i) = bits.index(food) {
+                bits.food.set(i);
+            }
+        }
+        for &hazard in board.hazards.iter() {
+            if let Some(i) = bits.index(hazard) {
+                bits.hazards.set(i);
+            }
+        }
+        bits
+    }
+
+    #[inline]
+    pub fn index(&self, coord: Coord) -> Option<usize> {
+        let (x, y) = (coord.x as UnitAbs, coord.y as UnitAbs);
+        if coord.x < 0 || coord.y < 0 || x >= self.width || y * self.width + x >= self.area {
+            None
+        } else {
+            Some(y * self.width + x)
+        }
+    }
+
+    #[cfg(test)]
+    pub fn coord(&self, i: usize) -> Coord {
+        Coord::new((i % self.width) as _, (i / self.width) as _)
+    }
+
+    //all spaces one step from any in `from`
+    pub fn neighbours(&self, from: Bitboard) -> Bitboard {
+        let w = self.width;
+        let mut result = (from.shl(1) & !self.first_col)
+            | (from.shr(1) & !self.last_col)
+            | from.shl(w)
+            | from.shr(w);
+        if self.wraps {
+            result |= (from & self.last_col).shr(w - 1)
+                | (from & self.first_col).shl(w - 1)
+                | (from & self.last_row).shr(self.area - w)
+                | (from & self.first_row).shl(self.area - w);
+        }
+        result & self.all
+    }
+
+    //spaces which will still be covered by a snake after `n_turns`
+    #[inline]
+    fn covered_after(&self, n_turns: usize) -> Bitboard {
+        self.covered.get(n_turns).or_else(|| self.covered.last()).copied().unwrap_or(Bitboard::EMPTY)
+    }
+
+    //moves from this point which are not obstructed or out of bounds. its safe to move into another snake if that
+    //node will be gone in n_turns. `board` must be the one these bits were built from
+    pub fn free_moves(&self, board: &Board, from: Coord, n_turns: usize) -> Vec<ApiDirection> {
+        let blocked = self.covered_after(n_turns);
+        ALL_DIRS.iter().copied().filter(|&dir| {
+            self.index(board.step(from, dir))
+                .map(|i| !blocked.get(i))
+                .unwrap_or(false)
+        }).collect()
+    }
+
+    //moves the snake at `mover` can make next turn without hitting a body, unless its allies let it through
+    pub fn snake_free_moves(&self, board: &Board, mover: usize) -> Vec<ApiDirection> {
+        let mut blocked = Bitboard::EMPTY;
+        for (snake_index, body) in self.bodies.iter().enumerate() {
+            let allied = snake_index != mover
+                && self.passthrough.get(mover).copied().flatten().is_some()
+                && self.passthrough[mover] == self.passthrough[snake_index];
+            if !allied {
+                blocked |= *body;
+            }
+        }
+        ALL_DIRS.iter().copied().filter(|&dir| {
+            self.index(board.step(self.heads[mover], dir))
+                .map(|i| !blocked.get(i))
+                .unwrap_or(false)
+        }).collect()
+    }
+
+    //simultaneous flood fill from every head. each space goes to the snake which can reach it first,
+    //and spaces reached first by more than one snake go to nobody
+    pub fn voronoi(&self) -> Vec<Bitboard> {
+        let mut owned = self.heads.iter()
+            .map(|&head| {
+                let mut bits = Bitboard::EMPTY;
+                if let Some(i) = self.index(head) {
+                    bits.set(i);
+                }
+                bits
+            })
+            .collect::<Vec<_>>();
+        let mut frontiers = owned.clone();
+        let mut claimed = owned.iter().fold(Bitboard::EMPTY, |claimed, bits| claimed | *bits);
+
+        let mut turn = 1;
+        loop {
+            let free = self.all & !self.covered_after(turn) & !claimed;
+            let mut reached = Bitboard::EMPTY;
+            let mut contested = Bitboard::EMPTY;
+            for frontier in frontiers.iter_mut() {
+                *frontier = self.neighbours(*frontier) & free;
+                contested |= reached & *frontier;
+                reached |= *frontier;
+            }
+            if reached.is_empty() {
+                break;
+            }
+            for (frontier, owned) in frontiers.iter_mut().zip(owned.iter_mut()) {
+                *frontier &= !contested;
+                *owned |= *frontier;
+            }
+            claimed |= reached;
+            turn += 1;
+        }
+        owned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{ApiGameState, ApiDirection::*};
+    use crate::game::{GameMode, ALL_MODES};
+
+    #[test]
+    fn test_shifts() {
+        let mut bits = Bitboard::EMPTY;
+        bits.set(0);
+        bits.set(63);
+        bits.set(100);
+        assert_eq!(bits.shl(70).iter().collect::<Vec<_>>(), vec![70, 133, 170]);
+        assert_eq!(bits.shr(40).iter().collect::<Vec<_>>(), vec![23, 60]);
+        assert_eq!(bits.shl(1).shr(1), bits);
+        assert_eq!(bits.count(), 3);
+        assert!(bits.shl(MAX_BITBOARD_AREA).is_empty());
+    }
+
+    #[test]
+    fn test_free_moves_match_board() {
+        let mut board = Board::from_api(&ApiGameState::parse_basic("
+        |  |  |  |  |  |  |  |
+        |  |  |A1|A0|  |  |  |
+        |  |  |A2|Y0|Y1|  |  |
+        |  |  |  |  |Y2|()|  |
+        |B2|B1|B0|  |Y3|  |  |
+        |  |  |  |  |Y4|Y5|  |
+        |  |  |  |  |  |  |  |
+        "));
+        //a stacked tail, and a squad to pass through in squad mode
+        let ally = board.snakes.iter().position(|snake| snake.head() == Coord::new(3, 1)).unwrap();
+        board.snakes[3 - ally].feed(100);
+        board.snakes[0].squad = Some(1);
+        board.snakes[ally].squad = Some(1);
+        for &mode in ALL_MODES.iter() {
+            board.set_mode(mode);
+            let bits = BoardBits::new(&board);
+            for i in 0..board.area() {
+                let coord = bits.coord(i);
+                for n_turns in 0..8 {
+                    assert_eq!(bits.free_moves(&board, coord, n_turns), board.get_free_moves(coord, n_turns), "{} {:?} {}", mode, coord, n_turns);
+                }
+            }
+            for (snake_index, snake) in board.snakes.iter().enumerate() {
+                assert_eq!(bits.snake_free_moves(&board, snake_index), board.get_snake_free_moves(snake.head(), 1, Some(snake_index)), "{} {}", mode, snake_index);
+            }
+            assert_eq!(bits.snake_free_moves(&board, 0).contains(&Up), mode == GameMode::Squad);
+        }
+    }
+
+    #[test]
+    fn test_wrapped_neighbours() {
+        let mut board = Board::from_api(&ApiGameState::parse_basic("
+        |  |  |  |
+        |  |  |  |
+        |Y0|  |  |
+        "));
+        board.set_mode(GameMode::Wrapped);
+        let bits = BoardBits::new(&board);
+        let mut corner = Bitboard::EMPTY;
+        corner.set(bits.index(board.you().head()).unwrap());
+        let neighbours = bits.neighbours(corner).iter().map(|i| bits.coord(i)).collect::<Vec<_>>();
+        assert_eq!(neighbours.len(), 4);
+        for dir in [Up, Down, Left, Right].iter() {
+            assert!(neighbours.contains(&board.step(board.you().head(), *dir)));
+        }
+    }
+
+    #[test]
+    fn test_voronoi() {
+        let board = Board::from_api(&ApiGameState::parse_basic("
+        |  |  |  |  |  |
+        |Y0|  |  |  |A0|
+        |Y1|  |  |  |A1|
+        |  |  |  |  |  |
+        "));
+        let owned = BoardBits::new(&board).voronoi();
+        //the middle column is contested, and tails are gone by the time anyone reaches them
+        assert_eq!(owned[0].count(), 8);
+        assert_eq!(owned[1].count(), 8);
+    }
+}
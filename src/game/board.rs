@@ -1,20 +1,27 @@
 use std::iter;
 use std::cmp::{Ord, Ordering, Eq, PartialEq, PartialOrd};
-use std::collections::{HashSet, HashMap, BinaryHeap};
+use std::collections::{HashMap, BinaryHeap};
 use std::fmt;
 use rand::prelude::*;
 use std::iter::FromIterator;
-use crate::api::{ApiGameState, ApiDirection, ALL_DIRS};
+use crate::api::{ApiGameState, ApiDirection, ApiSnake};
+#[cfg(test)]
+use crate::api::ALL_DIRS;
 use crate::util::cartesian_product;
-use super::snake::{Snake, Health};
+use super::snake::{Snake, Health, SquadId};
 use super::coord::{Coord, Unit, UnitAbs};
+use super::offset::Offset;
 use super::path::Path;
+use super::rules::{GameMode, Settings};
+use super::bitboard::BoardBits;
+use super::zobrist;
 use log::*;
 
 pub const SNAKE_MAX_HEALTH: Health = 100;
 pub const SNAKE_START_SIZE: UnitAbs = 3;
-pub const FOOD_SPAWN_CHANCE: u32 = 15; //of 100
-const ORIGIN: Coord = Coord {x: 0, y: 0};
+pub const ORIGIN: Coord = Coord {x: 0, y: 0};
+//boards hold at most this many snakes, so that undoing a turn needs no heap allocation
+pub const MAX_SNAKES: usize = 16;
 const PATHFINDING_HEURISTIC_WEIGHT: UnitAbs = 3;
 
 #[derive(Clone, Copy, PartialEq, Debug)]
@@ -24,6 +31,49 @@ pub enum CauseOfDeath {
     SelfCollision,
     OutOfBounds,
     Starved,
+    Hazard,
+    SquadEliminated,
+}
+
+//why, by whom, and on which turn a snake was removed from the board
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Elimination {
+    pub cause: CauseOfDeath,
+    //index of the responsible snake, from before any were removed
+    pub by: Option<usize>,
+    pub turn: u32,
+}
+
+//keyed by each eliminated snake's index from before any were removed
+pub type Eliminations = HashMap<usize, Elimination>;
+
+//how a snake was before a move, enough to put it back
+#[derive(Copy, Clone)]
+struct SnakeUndo {
+    health: Health,
+    size: usize,
+    head: Coord,
+    tail: Coord,
+}
+
+const NO_SNAKE: SnakeUndo = SnakeUndo {health: 0, size: 0, head: ORIGIN, tail: ORIGIN};
+
+//returned by `Board::apply` to revert it with `Board::undo`
+pub struct UndoToken {
+    //the first `n_snakes` are the board's snakes from before the move
+    snakes: [SnakeUndo; MAX_SNAKES],
+    n_snakes: usize,
+    hash: u64,
+    //the first `n_eaten` are food eaten this turn, with their indices beforehand, in ascending order
+    eaten: [(usize, Coord); MAX_SNAKES],
+    n_eaten: usize,
+    //how much food there was before any spawned
+    n_food: usize,
+    //all the food there was, if it was cleared without being eaten
+    cleared_food: Option<Vec<Coord>>,
+    //removed snakes by their original index, in ascending order
+    eliminated: Vec<(usize, Snake)>,
+    pub eliminations: Eliminations,
 }
 
 #[derive(Clone, PartialEq, Debug)]
@@ -31,12 +81,19 @@ pub struct Board {
     //must contain at least 1 snake (the `you` snake, at index 0)
     pub snakes: Vec<Snake>,
     pub food: Vec<Coord>,
+    pub hazards: Vec<Coord>,
     bound: Coord,
+    turn: u32,
+    mode: GameMode,
+    settings: Settings,
+    //zobrist hash of everything the heuristic sees besides the mode and settings, kept up to date by `apply` and `undo`
+    hash: u64,
 }
 
 #[derive(Copy, Clone, Debug)]
 pub struct Territory {
     pub area: UnitAbs,
+    pub hazard_area: UnitAbs,
     pub num_food: usize,
     pub nearest_food: Option<UnitAbs>,
 }
@@ -64,8 +121,8 @@ impl PartialEq for FrontierCoord {
 }
 
 impl Board {
-    pub fn init(width: UnitAbs, height: UnitAbs, num_snakes: usize) -> Result<Board, &'static str> {
-        let mut rng = rand::thread_rng();
+    //starting positions and food are drawn from `rng`, so a seeded one gives the same start every time
+    pub fn init<R: Rng>(width: UnitAbs, height: UnitAbs, num_snakes: usize, mode: GameMode, settings: Settings, rng: &mut R) -> Result<Board, &'static str> {
         let mut free_spaces: Vec<Coord> = Vec::from_iter(
             cartesian_product(&[
                 (0..width as Unit).collect(),
@@ -73,90 +130,204 @@ impl Board {
             ]).iter().map(|v| Coord::new(v[0], v[1]))
         );
 
-        let snakes = match (width, height) {
-            (7, 7) | (11, 11) | (19, 19) if num_snakes <= 8 => {
-                //the rules define 3 fixed board sizes with 8 fixed starting positions
-                let mn = 1 as Unit;
-                let md = ((width - 1) / 2) as Unit;
-                let mx = (width - 2) as Unit;
-                let mut fixed_starts = [
-                    Coord::new(mn, mn),
-                    Coord::new(mn, md),
-                    Coord::new(mn, mx),
-                    Coord::new(md, mn),
-                    Coord::new(md, mx),
-                    Coord::new(mx, mn),
-                    Coord::new(mx, md),
-                    Coord::new(mx, mx),
-                ];
-                fixed_starts.shuffle(&mut rng);
-                fixed_starts.iter().take(num_snakes).map(|start| {
-                    let i = free_spaces.iter().position(|coord| coord == start).unwrap();
-                    free_spaces.swap_remove(i);
-                    Snake::init(SNAKE_MAX_HEALTH, *start, SNAKE_START_SIZE)
-                }).collect()
-            },
-            _ => {
-                //otherwise, all snakes spawn in random positions if there's space
-                if free_spaces.len() < num_snakes {
-                    return Err("The board is not big enough to contain all requested snakes");
-                }
-                iter::repeat_with(|| {
-                    let start = free_spaces.swap_remove(rng.gen_range(0, free_spaces.len()));
-                    Snake::init(SNAKE_MAX_HEALTH, start, SNAKE_START_SIZE)
-                }).take(num_snakes).collect()
-            },
+        if num_snakes > MAX_SNAKES {
+            return Err("Too many snakes");
+        }
+
+        //the rules define 3 fixed board sizes with 8 fixed starting positions
+        let standard_size = matches!((width, height), (7, 7) | (11, 11) | (19, 19)) && num_snakes <= 8;
+        let mut snakes: Vec<Snake> = if standard_size {
+            let mn = 1 as Unit;
+            let md = ((width - 1) / 2) as Unit;
+            let mx = (width - 2) as Unit;
+            let mut fixed_starts = [
+                Coord::new(mn, mn),
+                Coord::new(mn, md),
+                Coord::new(mn, mx),
+                Coord::new(md, mn),
+                Coord::new(md, mx),
+                Coord::new(mx, mn),
+                Coord::new(mx, md),
+                Coord::new(mx, mx),
+            ];
+            fixed_starts.shuffle(rng);
+            fixed_starts.iter().take(num_snakes).map(|start| {
+                let i = free_spaces.iter().position(|coord| coord == start).unwrap();
+                free_spaces.swap_remove(i);
+                Snake::init(SNAKE_MAX_HEALTH, *start, SNAKE_START_SIZE)
+            }).collect()
+        } else {
+            //otherwise, all snakes spawn in random positions if there's space
+            if free_spaces.len() < num_snakes {
+                return Err("The board is not big enough to contain all requested snakes");
+            }
+            iter::repeat_with(|| {
+                let start = free_spaces.swap_remove(rng.gen_range(0, free_spaces.len()));
+                Snake::init(SNAKE_MAX_HEALTH, start, SNAKE_START_SIZE)
+            }).take(num_snakes).collect()
         };
+        if mode == GameMode::Squad {
+            //snakes are dealt into squads of two
+            for (i, snake) in snakes.iter_mut().enumerate() {
+                snake.squad = Some((i / 2) as SquadId);
+            }
+        }
 
-        let food_spawner = iter::repeat_with(|| {
-            if free_spaces.is_empty() {
-                warn!("Ran out of free space to spawn food");
-                None
-            } else {
-                Some(free_spaces.swap_remove(rng.gen_range(0, free_spaces.len())))
+        let mut food = Vec::new();
+        if mode.ruleset().initial_food() && standard_size {
+            //each snake gets food on a diagonal leading away from the center, then the center gets one too
+            let center = Coord::new(((width - 1) / 2) as Unit, ((height - 1) / 2) as Unit);
+            for snake in snakes.iter() {
+                let head = snake.head();
+                let options: Vec<Coord> = [(-1, -1), (-1, 1), (1, -1), (1, 1)].iter()
+                    .map(|&(dx, dy)| head + Offset::new(dx, dy))
+                    .filter(|&coord| {
+                        coord != center && free_spaces.contains(&coord) && (
+                            (coord.x < head.x && head.x < center.x) ||
+                            (center.x < head.x && head.x < coord.x) ||
+                            (coord.y < head.y && head.y < center.y) ||
+                            (center.y < head.y && head.y < coord.y)
+                        ) && !(
+                            //and never in a corner
+                            (coord.x == 0 || coord.x == (width - 1) as Unit) &&
+                            (coord.y == 0 || coord.y == (height - 1) as Unit)
+                        )
+                    })
+                    .collect();
+                if let Some(&coord) = options.choose(rng) {
+                    free_spaces.retain(|&free| free != coord);
+                    food.push(coord);
+                } else {
+                    warn!("No space for food near snake at {:?}", head);
+                }
+            }
+            if let Some(i) = free_spaces.iter().position(|&free| free == center) {
+                food.push(free_spaces.swap_remove(i));
             }
-        });
+        } else if mode.ruleset().initial_food() {
+            //one food per snake anywhere there's space
+            for _ in 0..num_snakes {
+                if free_spaces.is_empty() {
+                    warn!("Ran out of free space to spawn food");
+                    break;
+                }
+                food.push(free_spaces.swap_remove(rng.gen_range(0, free_spaces.len())));
+            }
+        }
 
-        Ok(Board {
+        let mut board = Board {
             snakes,
-            food: food_spawner
-                .take_while(Option::is_some)
-                .take(num_snakes)
-                .map(Option::unwrap)
-                .collect(),
+            food,
+            hazards: Vec::new(),
             bound: Coord::new(
                 width as Unit - 1,
                 height as Unit - 1
-            )
-        })
+            ),
+            turn: 0,
+            mode,
+            settings,
+            hash: 0,
+        };
+        board.rehash();
+        Ok(board)
     }
 
     pub fn from_api(game_state: &ApiGameState) -> Board {
-        Board {
-            snakes: iter::once(&game_state.you)
-                .chain(game_state.board.snakes.iter().filter(|s| s.id != game_state.you.id))
-                .map(|s| Snake::from_api(s).unwrap())
+        //squad names are numbered in the order they're first seen
+        let mut squad_names: Vec<&str> = Vec::new();
+        let mut board = Board {
+            snakes: Board::api_snakes(game_state)
+                .map(|s| {
+                    let mut snake = Snake::from_api(s).unwrap();
+                    snake.squad = s.squad.as_deref().filter(|name| !name.is_empty()).map(|name| {
+                        squad_names.iter().position(|&known| known == name).unwrap_or_else(|| {
+                            squad_names.push(name);
+                            squad_names.len() - 1
+                        }) as SquadId
+                    });
+                    snake
+                })
                 .collect(),
             food: game_state.board.food.iter().map(Coord::from).collect(),
+            hazards: game_state.board.hazards.iter().map(Coord::from).collect(),
             bound: Coord::new(
                 game_state.board.width as Unit - 1,
                 game_state.board.height as Unit - 1
             ),
-        }
+            turn: game_state.turn,
+            mode: game_state.game.ruleset.as_ref()
+                .map(|ruleset| ruleset.name.parse().unwrap_or_else(|e| {
+                    warn!("{}; falling back to standard rules", e);
+                    GameMode::Standard
+                }))
+                .unwrap_or(GameMode::Standard),
+            settings: game_state.game.ruleset.as_ref()
+                .map(|ruleset| Settings::from(&ruleset.settings))
+                .unwrap_or_default(),
+            hash: 0,
+        };
+        assert!(board.snakes.len() <= MAX_SNAKES, "Boards can have at most {} snakes", MAX_SNAKES);
+        board.rehash();
+        board
     }
 
-    //gets the set of moves from this point which are not obstructed or out of bounds
+    //the state's snakes in the order `from_api` puts them on the board: `you` first, then the rest in their original order.
+    //an empty `you`, as recorded once every snake has been eliminated, is left out
+    pub fn api_snakes(game_state: &ApiGameState) -> impl Iterator<Item = &ApiSnake> {
+        iter::once(&game_state.you)
+            .filter(|you| !you.body.is_empty())
+            .chain(game_state.board.snakes.iter().filter(move |s| s.id != game_state.you.id))
+    }
+
+    //whether the boards differ only by food, which searches don't predict the spawning of
+    pub fn same_except_food(&self, other: &Board) -> bool {
+        self.snakes == other.snakes
+            && self.hazards == other.hazards
+            && self.bound == other.bound
+            && self.turn == other.turn
+            && self.mode == other.mode
+            && self.settings == other.settings
+    }
+
+    //identifies the board's snakes and food; equal boards have equal hashes
+    pub fn zobrist_hash(&self) -> u64 {
+        self.hash
+    }
+
+    //recalculates the hash from scratch. needed after changing snakes, food or hazards directly
+    pub fn rehash(&mut self) {
+        let hash = zobrist::food_hash(&self.food) ^ zobrist::hazard_hash(&self.hazards) ^ zobrist::turn_key(self.turn);
+        self.hash = self.snakes.iter().enumerate()
+            .fold(hash, |hash, (snake_index, snake)| hash ^ zobrist::snake_hash(snake_index, snake));
+    }
+
+    //packed copy of the board for fast move generation and flood fills. building it is the costly part of those, so it's
+    //built once per board and passed to each of them
+    pub fn bits(&self) -> BoardBits {
+        BoardBits::new(self)
+    }
+
+    //gets the set of moves from this point which are not obstructed or out of bounds.
+    //its safe to move into another snake if that node will be gone in n_turns.
+    //the original linear scan, kept to check `BoardBits` against
+    #[cfg(test)]
     pub fn get_free_moves(&self, from: Coord, n_turns: usize) -> Vec<ApiDirection> {
+        self.get_snake_free_moves(from, n_turns, None)
+    }
+
+    //as above, but `mover` may pass through its allies' bodies if the rules allow
+    #[cfg(test)]
+    pub fn get_snake_free_moves(&self, from: Coord, n_turns: usize, mover: Option<usize>) -> Vec<ApiDirection> {
+        let tails_vacate = !self.mode.ruleset().always_grows();
+        let passes_through_allies = self.mode.ruleset().passes_through_allies(self);
         ALL_DIRS.iter().cloned().filter(|dir| {
-            let new_coord = from + *dir;
-            new_coord.bounded_by(ORIGIN, self.bound) && self.snakes.iter().all(|snake| {
-                if (new_coord - snake.head()).manhattan_dist() > snake.size() {
-                    //can save a little time ruling out snakes which are too far away
+            let new_coord = self.step(from, *dir);
+            self.contains(new_coord) && self.snakes.iter().enumerate().all(|(snake_index, snake)| {
+                if passes_through_allies && mover.map(|mover| self.allied(mover, snake_index)).unwrap_or(false) {
                     true
                 } else if let Some(i) = snake.find_first_node(new_coord, 0) {
                     //finding the "first" node is key to avoiding moving into stacked tail coords
-                    //its safe to move into another snake if that node will be gone in n_turns
-                    i >= snake.size().saturating_sub(n_turns)
+                    tails_vacate && i >= snake.size().saturating_sub(n_turns)
                 } else {
                     true
                 }
@@ -165,9 +336,9 @@ impl Board {
     }
 
     //find out where each snake can move to next. since snakes MUST move, if a snake is trapped, assume Up
-    pub fn enumerate_snake_moves(&self) -> Vec<Vec<ApiDirection>> {
-        self.snakes.iter().map(|snake| {
-            let mut moves = self.get_free_moves(snake.head(), 1);
+    pub fn enumerate_snake_moves(&self, bits: &BoardBits) -> Vec<Vec<ApiDirection>> {
+        (0..self.snakes.len()).map(|snake_index| {
+            let mut moves = bits.snake_free_moves(self, snake_index);
             if moves.is_empty() {
                 moves.push(ApiDirection::Up);
             }
@@ -176,13 +347,13 @@ impl Board {
     }
 
     //A* pathfinding
-    pub fn pathfind(&self, from: Coord, to: Coord) -> Option<Path> {
+    pub fn pathfind(&self, bits: &BoardBits, from: Coord, to: Coord) -> Option<Path> {
         //heap keeps open set sorted by best f_score
         let mut frontier: BinaryHeap<FrontierCoord> = BinaryHeap::new();
         //keeping known dists and breadcrumbs together in one tuple reduces hash operations
         let mut history: HashMap<Coord, (UnitAbs, Option<Coord>)> = HashMap::new();
         //static weighting: https://en.wikipedia.org/wiki/A*_search_algorithm#Bounded_relaxation
-        frontier.push(FrontierCoord(from, (to - from).manhattan_dist() * PATHFINDING_HEURISTIC_WEIGHT));
+        frontier.push(FrontierCoord(from, self.dist(from, to) * PATHFINDING_HEURISTIC_WEIGHT));
         history.insert(from, (0, None));
 
         while let Some(FrontierCoord(leader, _leader_f_score)) = frontier.pop() {
@@ -197,18 +368,18 @@ impl Board {
             let leader_g_score = history.get(&leader).map(|(g_score, ..)| *g_score).unwrap_or(0);
 
             //use g_score as number of turns in the future so we can shorten snake tails
-            let free_spaces = self.get_free_moves(leader, leader_g_score).iter()
-                .map(|dir| leader + *dir)
+            let free_spaces = bits.free_moves(self, leader, leader_g_score).iter()
+                .map(|dir| self.step(leader, *dir))
                 .collect::<Vec<_>>();
 
             //todo: try JPS https://zerowidth.com/2013/a-visual-explanation-of-jump-point-search.html
             for free_space in free_spaces {
                 let new_g_score = leader_g_score + 1;
                 let old_g_score = history.get(&free_space);
-                if old_g_score.is_none() || new_g_score < (*old_g_score.unwrap()).0 {
+                if old_g_score.is_none() || new_g_score < old_g_score.unwrap().0 {
                     //todo: https://github.com/riscy/a_star_on_grids#avoid-recomputing-heuristics
                     history.insert(free_space, (new_g_score, Some(leader)));
-                    let new_f_score = new_g_score + (to - free_space).manhattan_dist() * PATHFINDING_HEURISTIC_WEIGHT;
+                    let new_f_score = new_g_score + self.dist(free_space, to) * PATHFINDING_HEURISTIC_WEIGHT;
                     frontier.push(FrontierCoord(free_space, new_f_score));
                 }
             }
@@ -217,51 +388,40 @@ impl Board {
         None
     }
 
-    pub fn get_territories(&self) -> Vec<Territory> {
-        let mut ownerships = HashMap::<Coord, usize>::new();
-        let mut frontier = HashSet::<(Coord, usize)>::new();
-        for (i, snake) in self.snakes.iter().enumerate() {
-            let head = snake.head();
-            ownerships.insert(head, i);
-            frontier.insert((head, i));
-        }
-
-        let mut turn: usize = 1;
-        while !frontier.is_empty() {
-            let mut next_frontier = HashSet::<(Coord, usize)>::new();
-            for (coord, owner) in frontier.iter() {
-                for dir in self.get_free_moves(*coord, turn).iter() {
-                    let neighbour = *coord + *dir;
-                    if !ownerships.contains_key(&neighbour) {
-                        ownerships.insert(neighbour, *owner);
-                        next_frontier.insert((neighbour, *owner));
-                    }
-                }
+    pub fn get_territories(&self, bits: &BoardBits) -> Vec<Territory> {
+        let owned = bits.voronoi();
+        let mut territories = owned.iter()
+            .map(|owned| Territory {
+                area: owned.count(),
+                hazard_area: (*owned & bits.hazards).count(),
+                nearest_food: None,
+                num_food: 0,
+            })
+            .collect::<Vec<_>>();
+        for &coord in self.food.iter() {
+            let owner = match bits.index(coord).and_then(|i| owned.iter().position(|owned| owned.get(i))) {
+                Some(owner) => owner,
+                None => continue,
+            };
+            let mut food_dist = self.dist(self.snakes.get(owner).unwrap().head(), coord);
+            if self.is_hazard(coord) {
+                //reaching food in a hazard costs about as much health as this many extra turns
+                food_dist += self.settings.hazard_damage as UnitAbs;
             }
-            frontier = next_frontier;
-            turn += 1;
-        }
-
-        let mut territories = vec![Territory {area: 0, nearest_food: None, num_food: 0}; self.snakes.len()];
-        for (&coord, &owner) in ownerships.iter() {
-            if self.find_food(coord).is_some() {
-                let food_dist = (self.snakes.get(owner).unwrap().head() - coord).manhattan_dist();
-                if territories.get(owner).unwrap().nearest_food.map(|f| f < food_dist).unwrap_or(true) {
-                    territories.get_mut(owner).unwrap().nearest_food = Some(food_dist);
-                }
-                territories.get_mut(owner).unwrap().num_food += 1;
+            let territory = territories.get_mut(owner).unwrap();
+            if territory.nearest_food.map(|f| f < food_dist).unwrap_or(true) {
+                territory.nearest_food = Some(food_dist);
             }
-            territories.get_mut(owner).unwrap().area += 1;
+            territory.num_food += 1;
         }
         territories
     }
-
     //returns a list of snake indices sorted by increasing distance from the given point
     pub fn get_closest_snakes_by_manhattan(&self, coord: Coord) -> Vec<(usize, UnitAbs)> {
         let mut sorted_snakes = self.snakes.iter()
             .enumerate()
             .map(|(i, snake)| {
-                let dist = (coord - snake.head()).manhattan_dist();
+                let dist = self.dist(coord, snake.head());
                 (i, dist) //make sure to include original index before sorting
             })
             .collect::<Vec<_>>();
@@ -269,123 +429,177 @@ impl Board {
         sorted_snakes
     }
 
-    //todo: baggage from old territory code... can this still be useful?
-    pub fn get_closest_snake_by_pathfind(&self, coord: Coord) -> Option<(usize, UnitAbs)> {
-        let mut best_snake: Option<(usize, UnitAbs)> = None;
-
-        //sort snakes by their best case distances -- it's likely we can skip checking most of them
-        let mut sorted_snakes = self.snakes.iter()
-            .enumerate()
-            .map(|(i, snake)| (i, snake)) //make sure to include original index before sorting
-            .collect::<Vec<_>>();
-        sorted_snakes.sort_unstable_by_key(|(_, snake)| (coord - snake.head()).manhattan_dist());
-
-        for (i, snake) in sorted_snakes.iter() {
-            let snake_head = snake.head();
+    //Applies the game mode's rules to the board, returning indices of snakes that died. food is only spawned given an rng
+    pub fn advance(&mut self, food_rng: Option<&mut dyn RngCore>, snake_moves: &[ApiDirection]) -> Eliminations {
+        self.apply(food_rng, snake_moves).eliminations
+    }
 
-            //because snakes are sorted by best case distance, can finish early if we can't do any better
-            if let Some((_, best_dist)) = best_snake.as_ref() {
-                if (coord - snake_head).manhattan_dist() > *best_dist {
-                    break;
+    //like `advance`, but in place so that `undo` can restore the board exactly
+    pub fn apply(&mut self, food_rng: Option<&mut dyn RngCore>, snake_moves: &[ApiDirection]) -> UndoToken {
+        let ruleset = self.mode.ruleset();
+        let n_snakes = self.snakes.len();
+        let mut snakes = [NO_SNAKE; MAX_SNAKES];
+        for (before, snake) in snakes.iter_mut().zip(self.snakes.iter()) {
+            *before = SnakeUndo {health: snake.health, size: snake.size(), head: snake.head(), tail: snake.tail()};
+        }
+        self.turn += 1;
+        ruleset.move_snakes(self, snake_moves);
+        let hazard_starved = ruleset.damage_snakes(self);
+
+        //eaten food is enough to put it back. constrictor clears food without it being eaten, which only happens once
+        let mut eaten = [(0, ORIGIN); MAX_SNAKES];
+        let mut n_eaten = 0;
+        let cleared_food = if ruleset.always_grows() && !self.food.is_empty() {
+            Some(self.food.clone())
+        } else {
+            for snake in self.snakes.iter() {
+                if let Some(food_index) = self.find_food(snake.head()) {
+                    if eaten[..n_eaten].iter().all(|&(eaten_index, _)| eaten_index != food_index) {
+                        eaten[n_eaten] = (food_index, snake.head());
+                        n_eaten += 1;
+                    }
                 }
             }
+            eaten[..n_eaten].sort_unstable_by_key(|&(food_index, _)| food_index);
+            None
+        };
+        ruleset.feed_snakes(self);
+        let n_food = self.food.len();
+        if let Some(rng) = food_rng {
+            ruleset.spawn_food(self, rng);
+        }
 
-            if let Some(path) = self.pathfind(snake_head, coord) {
-                let path_dist = path.dist();
-                if best_snake.is_none() || path_dist < best_snake.as_ref().unwrap().1 {
-                    best_snake = Some((*i, path_dist));
+        let eliminations = ruleset.eliminate_snakes(self, &hazard_starved);
+        let mut eliminated = Vec::new();
+        if !eliminations.is_empty() {
+            for snake_index in (0..self.snakes.len()).rev() {
+                if eliminations.contains_key(&snake_index) {
+                    eliminated.push((snake_index, self.snakes.remove(snake_index)));
                 }
             }
+            eliminated.reverse();
         }
+        ruleset.end_turn(self);
 
-        best_snake
-    }
-
-    //Applies known game rules to the board, returning indices of snakes that died
-    pub fn advance(&mut self, spawn_food: bool, snake_moves: &[ApiDirection]) -> HashMap<usize, CauseOfDeath> {
-        let mut eaten_food: HashSet<usize> = HashSet::new();
-
-        //move snakes and find eaten food
-        for snake_index in 0..self.snakes.len() {
-            let dir = snake_moves.get(snake_index).cloned().unwrap_or_else(|| self.snakes[snake_index].get_default_move());
-            self.snakes[snake_index].slither(dir);
-            if let Some(food_index) = self.find_food(self.snakes[snake_index].head()) {
-                //all snakes get a chance to eat fairly before food is removed
-                self.snakes[snake_index].feed(SNAKE_MAX_HEALTH);
-                eaten_food.insert(food_index);
+        let hash = self.hash;
+        if eliminations.is_empty() {
+            self.hash ^= zobrist::turn_key(self.turn - 1) ^ zobrist::turn_key(self.turn);
+            for (snake_index, (snake, before)) in self.snakes.iter().zip(snakes.iter()).enumerate() {
+                self.hash ^= zobrist::snake_move_delta(snake_index, snake, before.head, before.tail, before.size, before.health);
             }
+            self.hash ^= match cleared_food.as_ref() {
+                Some(food) => zobrist::food_hash(food),
+                None => eaten[..n_eaten].iter().fold(0, |hash, &(_, coord)| hash ^ zobrist::food_key(coord)),
+            };
+            self.hash ^= zobrist::food_hash(&self.food[n_food..]);
+        } else {
+            //remaining snakes have new indices
+            self.rehash();
         }
+        UndoToken {snakes, n_snakes, hash, eaten, n_eaten, n_food, cleared_food, eliminated, eliminations}
+    }
 
-        let dead_snakes = self.snakes.iter().enumerate().filter_map(|(snake_index, snake)| {
-            if snake.starved() {
-                return Some((snake_index, CauseOfDeath::Starved));
-            }
-            if !snake.head().bounded_by(ORIGIN, self.bound) {
-                return Some((snake_index, CauseOfDeath::OutOfBounds));
-            }
-            for (other_snake_index, other_snake) in self.snakes.iter().enumerate() {
-                if other_snake_index != snake_index {
-                    if let Some(i) = other_snake.find_first_node(snake.head(), 0) {
-                        if i > 0 {
-                            return Some((snake_index, CauseOfDeath::OtherCollision));
-                        } else if snake.size() <= other_snake.size() {
-                            //TWO SNAKES ENTER, ONE SNAKE LEAVES (Ok, actually neither may leave)
-                            return Some((snake_index, CauseOfDeath::HeadToHead));
-                        }
-                    }
-                } else if other_snake.find_first_node(snake.head(), 1).is_some() {
-                    return Some((snake_index, CauseOfDeath::SelfCollision));
-                }
-            }
-            None
-        }).collect::<HashMap<usize, CauseOfDeath>>();
-
-        //clean up
-        if !eaten_food.is_empty() {
-            self.food = self.food.iter().enumerate()
-                .filter_map(|(i, f)| if eaten_food.contains(&i) {None} else {Some(*f)})
-                .collect();
+    //reverts the `apply` which returned `token`. tokens must be undone in reverse order
+    pub fn undo(&mut self, token: UndoToken) {
+        for (snake_index, snake) in token.eliminated {
+            self.snakes.insert(snake_index, snake);
         }
-        if !dead_snakes.is_empty() {
-            self.snakes = self.snakes.iter().enumerate()
-                .filter_map(|(i, s)| {
-                    if dead_snakes.contains_key(&i) {
-                        None
-                    } else {
-                        Some(s.clone())
-                    }
-                })
-                .collect();
+        for (snake, before) in self.snakes.iter_mut().zip(token.snakes[..token.n_snakes].iter()) {
+            //drop any growth, then slide back
+            snake.body.nodes.truncate(before.size);
+            snake.body.pop_start();
+            snake.body.nodes.push_back(before.tail);
+            snake.health = before.health;
         }
+        match token.cleared_food {
+            Some(food) => self.food = food,
+            None => {
+                self.food.truncate(token.n_food);
+                for &(food_index, coord) in token.eaten[..token.n_eaten].iter() {
+                    self.food.insert(food_index, coord);
+                }
+            },
+        }
+        self.hash = token.hash;
+        self.turn -= 1;
+    }
 
-        if spawn_food {
-            let mut rng = rand::thread_rng();
-            if self.food.is_empty() || rng.gen_range(0, 100) <= FOOD_SPAWN_CHANCE {
-                let free_spaces: Vec<Coord> = Vec::from_iter(
-                    cartesian_product(&[
-                        (0..self.width() as Unit).collect(),
-                        (0..self.height() as Unit).collect()
-                    ]).iter().filter_map(|v| {
-                        let coord = Coord::new(v[0], v[1]);
-                        for snake in self.snakes.iter() {
-                            if snake.find_first_node(coord, 0).is_some() {
-                                return None;
-                            }
-                        }
-                        Some(coord)
-                    })
-                );
-                self.food.push(*free_spaces.get(rng.gen_range(0, free_spaces.len())).unwrap());
-            }
+    pub fn game_over(&self) -> bool {
+        self.mode.ruleset().game_over(self)
+    }
+
+    //the turn this board is at; advancing a board begins the next one
+    pub fn turn(&self) -> u32 {
+        self.turn
+    }
+
+    pub fn mode(&self) -> GameMode {
+        self.mode
+    }
+
+    #[cfg(test)]
+    pub fn set_mode(&mut self, mode: GameMode) {
+        self.mode = mode;
+    }
+
+    pub fn settings(&self) -> Settings {
+        self.settings
+    }
+
+    #[cfg(test)]
+    pub fn set_settings(&mut self, settings: Settings) {
+        self.settings = settings;
+    }
+
+    #[inline]
+    pub fn is_hazard(&self, coord: Coord) -> bool {
+        self.hazards.contains(&coord)
+    }
+
+    #[inline]
+    pub fn contains(&self, coord: Coord) -> bool {
+        coord.bounded_by(ORIGIN, self.bound)
+    }
+
+    //whether two different snakes are in the same squad
+    #[inline]
+    pub fn allied(&self, a: usize, b: usize) -> bool {
+        a != b && self.snakes[a].squad.is_some() && self.snakes[a].squad == self.snakes[b].squad
+    }
+
+    pub fn bound(&self) -> Coord {
+        self.bound
+    }
+
+    pub fn wraps(&self) -> bool {
+        self.mode.ruleset().wraps()
+    }
+
+    //the space reached by moving from `from`, which may be off the board unless it wraps
+    #[inline]
+    pub fn step(&self, from: Coord, dir: ApiDirection) -> Coord {
+        if self.wraps() {
+            (from + dir).wrap(self.bound)
+        } else {
+            from + dir
         }
+    }
 
-        dead_snakes
+    //manhattan distance between two spaces, taking the short way around a wrapped board
+    #[inline]
+    pub fn dist(&self, a: Coord, b: Coord) -> UnitAbs {
+        if self.wraps() {
+            (b - a).wrapped_manhattan_dist(self.width(), self.height())
+        } else {
+            (b - a).manhattan_dist()
+        }
     }
 
     pub fn you(&self) -> &Snake {
         self.snakes.first().unwrap()
     }
 
+    #[cfg(test)]
     pub fn enemies(&self) -> &[Snake] {
         if self.snakes.len() > 1 {
             &self.snakes[1..]
@@ -407,7 +621,7 @@ impl Board {
         self.width() * self.height()
     }
 
-    fn find_food(&self, coord: Coord) -> Option<usize> {
+    pub fn find_food(&self, coord: Coord) -> Option<usize> {
         self.food.iter().position(|&food| food == coord)
     }
 }
@@ -421,9 +635,11 @@ impl fmt::Display for Board {
 //todo: write more tests (head-to-head, head-to-body, pathfinding, territories, free moves, ...)
 #[cfg(test)]
 mod tests {
-    use serde_json;
     use super::*;
     use crate::api::ApiDirection::*;
+    use crate::game::ALL_MODES;
+    use crate::api::ALL_DIRS;
+    use rand::rngs::StdRng;
 
     macro_rules! advance {
         ($moves:expr, $curr:expr) => (
@@ -431,7 +647,7 @@ mod tests {
                 let game_state = ApiGameState::parse_basic($curr);
                 let prev = Board::from_api(&game_state);
                 let mut next = prev.clone();
-                let result = next.advance(false, $moves);
+                let result = next.advance(None, $moves);
                 (prev, next, result)
             }
         );
@@ -566,6 +782,7 @@ mod tests {
         //no food was eaten
         assert_eq!(prev.food, next.food);
         assert_eq!(prev.food, next.food);
+        assert_eq!(next.turn(), prev.turn() + 1);
         //health of snakes goes down each turn
         assert_eq!(next.you().health, prev.you().health - 1);
         //snake A hit a wall
@@ -574,6 +791,127 @@ mod tests {
         assert_eq!(next.you().head(), Coord::new(2, 0));
     }
 
+    #[test]
+    fn test_wrapped_moves() {
+        let mut board = Board::from_api(&ApiGameState::parse_basic("
+        |Y0|  |  |A3|A2|
+        |Y1|  |  |  |A1|
+        |Y2|  |  |  |A0|
+        |  |  |  |  |  |
+        |  |  |  |  |  |
+        "));
+        assert_eq!(board.get_free_moves(board.you().head(), 1), vec![Right]);
+        assert_eq!(board.dist(board.you().head(), board.enemies()[0].head()), 6);
+
+        board.set_mode(GameMode::Wrapped);
+        let free_moves = board.get_free_moves(board.you().head(), 1);
+        assert_eq!(free_moves.len(), 2);
+        assert!(free_moves.contains(&Up));
+        assert!(free_moves.contains(&Right));
+        assert_eq!(board.dist(board.you().head(), board.enemies()[0].head()), 3);
+
+        //shortest way is off the top and left edges
+        let path = board.pathfind(&board.bits(), board.you().head(), Coord::new(4, 3)).unwrap();
+        assert_eq!(path.num_nodes(), 4);
+
+        let dead = board.advance(None, &[Up, Right]);
+        assert!(dead.is_empty());
+        assert_eq!(board.you().head(), Coord::new(0, 4));
+        assert_eq!(board.enemies()[0].head(), Coord::new(0, 2));
+        assert_eq!(board.you().get_default_move(), Up);
+        assert_eq!(board.enemies()[0].get_default_move(), Right);
+    }
+
+    #[test]
+    fn test_constrictor_tails_stay() {
+        let mut board = Board::from_api(&ApiGameState::parse_basic("
+        |  |  |  |
+        |Y0|Y1|  |
+        |Y3|Y2|  |
+        "));
+        assert_eq!(board.get_free_moves(board.you().head(), 2), vec![Down, Up]);
+        board.set_mode(GameMode::Constrictor);
+        assert_eq!(board.get_free_moves(board.you().head(), 2), vec![Up]);
+    }
+
+    #[test]
+    fn test_init_food() {
+        let board = Board::init(11, 11, 4, GameMode::Standard, Settings::default(), &mut rand::thread_rng()).unwrap();
+        let center = Coord::new(5, 5);
+        assert_eq!(board.food.len(), 5);
+        assert!(board.food.contains(&center));
+        for snake in board.snakes.iter() {
+            let head = snake.head();
+            assert!(board.food.iter().any(|&food| {
+                let offset = food - head;
+                offset.dx.abs() == 1 && offset.dy.abs() == 1 && board.dist(food, center) >= board.dist(head, center)
+            }));
+        }
+
+        //every start is used with 8 snakes, including those next to corners, which never get food in the corner
+        for seed in 0..20 {
+            let board = Board::init(7, 7, 8, GameMode::Standard, Settings::default(), &mut StdRng::seed_from_u64(seed)).unwrap();
+            assert!(board.snakes.iter().any(|snake| snake.head() == Coord::new(1, 1)));
+            for corner in [Coord::new(0, 0), Coord::new(0, 6), Coord::new(6, 0), Coord::new(6, 6)].iter() {
+                assert!(!board.food.contains(corner));
+            }
+        }
+
+        let board = Board::init(11, 11, 4, GameMode::Constrictor, Settings::default(), &mut rand::thread_rng()).unwrap();
+        assert!(board.food.is_empty());
+        let board = Board::init(5, 5, 2, GameMode::Standard, Settings::default(), &mut rand::thread_rng()).unwrap();
+        assert_eq!(board.food.len(), 2);
+        assert!(Board::init(19, 19, MAX_SNAKES + 1, GameMode::Standard, Settings::default(), &mut rand::thread_rng()).is_err());
+    }
+
+    #[test]
+    fn test_seeded() {
+        //the same seed gives the same starts and food
+        let play = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut board = Board::init(11, 11, 4, GameMode::Standard, Settings::default(), &mut rng).unwrap();
+            let mut history = vec![board.clone()];
+            while !board.game_over() && board.turn() < 50 {
+                let moves = board.snakes.iter().map(|snake| snake.get_default_move()).collect::<Vec<_>>();
+                board.advance(Some(&mut rng), &moves);
+                history.push(board.clone());
+            }
+            history
+        };
+        assert_eq!(play(1), play(1));
+        assert_ne!(play(1), play(2));
+    }
+
+    #[test]
+    fn test_apply_undo() {
+        let mut rng = StdRng::seed_from_u64(14);
+        for &mode in ALL_MODES.iter() {
+            for _ in 0..10 {
+                let mut board = Board::init(7, 7, 4, mode, Settings::default(), &mut rng).unwrap();
+                board.hazards = (0..7).map(|y| Coord::new(0, y)).collect();
+                board.rehash();
+                let mut history = Vec::new();
+                while !board.game_over() && board.turn() < 200 {
+                    //mostly sensible moves so games last, with the odd blunder
+                    let moves = board.enumerate_snake_moves(&board.bits()).iter()
+                        .map(|dirs| if rng.gen_bool(0.1) {*ALL_DIRS.choose(&mut rng).unwrap()} else {*dirs.choose(&mut rng).unwrap()})
+                        .collect::<Vec<_>>();
+                    let before = board.clone();
+                    let spawn_food = rng.gen_bool(0.5);
+                    let token = board.apply(if spawn_food {Some(&mut rng)} else {None}, &moves);
+                    let mut rehashed = board.clone();
+                    rehashed.rehash();
+                    assert_eq!(board.zobrist_hash(), rehashed.zobrist_hash());
+                    history.push((before, token));
+                }
+                while let Some((before, token)) = history.pop() {
+                    board.undo(token);
+                    assert_eq!(board, before);
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_you_die() {
         let (prev, next, result) = advance!(&[Up], "
@@ -585,6 +923,6 @@ mod tests {
         ");
         assert_eq!(prev.you().size(), 9);
         assert_eq!(next.snakes.len(), 0);
-        assert!(!result.is_empty());
+        assert_eq!(result.get(&0), Some(&Elimination {cause: CauseOfDeath::SelfCollision, by: Some(0), turn: 1}));
     }
 }
@@ -60,6 +60,15 @@ impl Coord {
         self.x >= x_min && self.x <= x_max &&
             self.y >= y_min && self.y <= y_max
     }
+
+    //brings a coord which has left the origin..=bound rectangle back in from the opposite edge
+    #[inline]
+    pub fn wrap(self, bound: Coord) -> Coord {
+        Coord {
+            x: self.x.rem_euclid(bound.x + 1),
+            y: self.y.rem_euclid(bound.y + 1),
+        }
+    }
 }
 
 impl Add<ApiDirection> for Coord {
@@ -157,6 +166,14 @@ mod tests {
         assert_eq!(a, Coord {x: 1, y: 2});
     }
 
+    #[test]
+    fn test_wrap() {
+        let bound = Coord {x: 10, y: 10};
+        assert_eq!(Coord {x: 5, y: 5}.wrap(bound), Coord {x: 5, y: 5});
+        assert_eq!(Coord {x: -1, y: 11}.wrap(bound), Coord {x: 10, y: 0});
+        assert_eq!(Coord {x: 11, y: -1}.wrap(bound), Coord {x: 0, y: 10});
+    }
+
     #[test]
     fn test_bound() {
         assert!(Coord {x: 5, y: 5}.bounded_by(
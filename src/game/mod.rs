@@ -3,9 +3,16 @@ mod offset;
 mod path;
 mod snake;
 mod board;
+mod bitboard;
+mod zobrist;
+mod rules;
+#[cfg(test)]
+mod parity;
 
 pub use coord::*;
 pub use offset::*;
 pub use path::*;
 pub use snake::*;
 pub use board::*;
+pub use bitboard::*;
+pub use rules::*;
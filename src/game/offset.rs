@@ -54,22 +54,32 @@ impl Offset {
     }
 
     #[inline]
+    #[cfg(test)]
     pub fn linear(self) -> bool {
         (self.dx == 0) ^ (self.dy == 0)
     }
 
     #[inline]
     pub fn manhattan_dist(self) -> UnitAbs {
-        self.dx.abs() as UnitAbs + self.dy.abs() as UnitAbs
+        self.dx.unsigned_abs() as UnitAbs + self.dy.unsigned_abs() as UnitAbs
     }
 
+    //the short way around for an offset between neighbours on a wrapped board, e.g. (-10, 0) becomes (1, 0)
     #[inline]
-    pub fn abs(&self) -> Offset {
+    pub fn unwrap_neighbour(self) -> Offset {
         Offset {
-            dx: self.dx.abs(),
-            dy: self.dy.abs(),
+            dx: if self.dx.abs() > 1 {-self.dx.signum()} else {self.dx},
+            dy: if self.dy.abs() > 1 {-self.dy.signum()} else {self.dy},
         }
     }
+
+    //manhattan distance when travel may wrap around a board of the given size
+    #[inline]
+    pub fn wrapped_manhattan_dist(self, width: UnitAbs, height: UnitAbs) -> UnitAbs {
+        let dx = self.dx.unsigned_abs() as UnitAbs % width;
+        let dy = self.dy.unsigned_abs() as UnitAbs % height;
+        dx.min(width - dx) + dy.min(height - dy)
+    }
 }
 
 impl AddAssign for Offset {
@@ -148,5 +158,17 @@ mod tests {
         assert_eq!(Offset::new(-10, 10).manhattan_dist(), 20);
         assert_eq!(Offset::new(1, 2).manhattan_dist(), 3);
         assert_eq!(Offset::new(2, 1).manhattan_dist(), 3);
+
+        assert_eq!(Offset::new(10, 0).wrapped_manhattan_dist(11, 11), 1);
+        assert_eq!(Offset::new(-6, 5).wrapped_manhattan_dist(11, 11), 10);
+        assert_eq!(Offset::new(2, -9).wrapped_manhattan_dist(11, 11), 4);
+    }
+
+    #[test]
+    fn test_unwrap_neighbour() {
+        assert_eq!(Offset::new(-10, 0).unwrap_neighbour(), Offset::new(1, 0));
+        assert_eq!(Offset::new(0, 6).unwrap_neighbour(), Offset::new(0, -1));
+        assert_eq!(Offset::new(0, -1).unwrap_neighbour(), Offset::new(0, -1));
+        assert_eq!(ZERO.unwrap_neighbour(), ZERO);
     }
 }
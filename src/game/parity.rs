@@ -0,0 +1,259 @@
+//transitions from the official engine's rules, replayed through `Board::advance`.
+//each turn is: move, reduce health, damage hazards, feed, spawn food, then eliminate
+use std::collections::HashMap;
+use std::fs;
+use serde::Deserialize;
+use crate::api::{ApiBoard, ApiGameState, ApiDirection, ApiDirection::*, ApiSnakeId};
+use super::board::{Board, CauseOfDeath};
+use super::coord::{Coord, Unit};
+use super::rules::GameMode;
+use super::snake::{Snake, Health};
+
+struct Outcome {
+    snakes: HashMap<String, Snake>,
+    food: Vec<Coord>,
+    eliminated: HashMap<String, CauseOfDeath>,
+}
+
+//moves and results are by snake name, since enemy order on the board isn't fixed
+fn replay(mode: GameMode, before: &str, moves: &[(&str, ApiDirection)]) -> Outcome {
+    let game_state = ApiGameState::parse_basic(before);
+    let names = Board::api_snakes(&game_state).map(|s| s.name.clone()).collect::<Vec<_>>();
+    let mut board = Board::from_api(&game_state);
+    board.set_mode(mode);
+    let snake_moves = names.iter()
+        .map(|name| moves.iter().find(|(n, _)| n == name).map(|&(_, dir)| dir).unwrap())
+        .collect::<Vec<_>>();
+
+    let eliminated = board.advance(None, &snake_moves).into_iter()
+        .map(|(snake_index, elimination)| (names[snake_index].clone(), elimination.cause))
+        .collect::<HashMap<_, _>>();
+    let survivors = names.iter().enumerate().filter(|(i, _)| !eliminated.contains_key(&names[*i])).map(|(_, name)| name.clone());
+    Outcome {
+        snakes: survivors.zip(board.snakes.iter().cloned()).collect(),
+        food: board.food.clone(),
+        eliminated,
+    }
+}
+
+fn assert_snake(outcome: &Outcome, name: &str, health: Health, body: &[(Unit, Unit)]) {
+    let snake = outcome.snakes.get(name).unwrap();
+    assert_eq!(snake.health, health);
+    assert_eq!(snake.body.nodes, body.iter().map(|&(x, y)| Coord::new(x, y)).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_eating_stacks_tail() {
+    let outcome = replay(GameMode::Standard, "
+    |  |  |  |
+    |  |()|  |
+    |  |Y0|  |
+    |  |Y1|  |
+    |  |Y2|  |
+    ", &[("Y", Up)]);
+    assert_snake(&outcome, "Y", 100, &[(1, 1), (1, 2), (1, 3), (1, 3)]);
+    assert!(outcome.food.is_empty());
+
+    //the stacked tail stays put for one move
+    let outcome = replay(GameMode::Standard, "
+    |  |  |  |
+    |  |Y0|  |
+    |  |Y1|  |
+    |  |Y3|  |
+    ", &[("Y", Up)]);
+    assert_snake(&outcome, "Y", 99, &[(1, 0), (1, 1), (1, 2), (1, 3)]);
+}
+
+#[test]
+fn test_stacked_tail_blocks() {
+    //A ate last turn, so its tail is stacked (A2 is under A3)
+    let stacked = "
+    |  |  |  |
+    |  |  |A0|
+    |Y0|A3|A1|
+    |Y1|  |  |
+    |Y2|  |  |
+    ";
+    let outcome = replay(GameMode::Standard, stacked, &[("Y", Right), ("A", Up)]);
+    assert_eq!(outcome.eliminated.get("Y"), Some(&CauseOfDeath::OtherCollision));
+    assert_snake(&outcome, "A", 99, &[(2, 0), (2, 1), (2, 2), (1, 2)]);
+
+    //an ordinary tail moves out of the way in time
+    let outcome = replay(GameMode::Standard, "
+    |  |  |  |
+    |  |  |A0|
+    |Y0|A2|A1|
+    |Y1|  |  |
+    |Y2|  |  |
+    ", &[("Y", Right), ("A", Up)]);
+    assert!(outcome.eliminated.is_empty());
+    assert_snake(&outcome, "Y", 99, &[(1, 2), (0, 2), (0, 3)]);
+}
+
+#[test]
+fn test_head_to_head() {
+    let outcome = replay(GameMode::Standard, "
+    |  |  |  |
+    |Y0|  |A0|
+    |Y1|  |A1|
+    |Y2|  |A2|
+    ", &[("Y", Right), ("A", Left)]);
+    assert_eq!(outcome.eliminated.get("Y"), Some(&CauseOfDeath::HeadToHead));
+    assert_eq!(outcome.eliminated.get("A"), Some(&CauseOfDeath::HeadToHead));
+
+    let outcome = replay(GameMode::Standard, "
+    |  |  |  |
+    |Y0|  |A0|
+    |Y1|  |A1|
+    |Y2|  |A2|
+    |Y3|  |  |
+    ", &[("Y", Right), ("A", Left)]);
+    assert_eq!(outcome.eliminated.get("A"), Some(&CauseOfDeath::HeadToHead));
+    assert_snake(&outcome, "Y", 99, &[(1, 1), (0, 1), (0, 2), (0, 3)]);
+}
+
+#[test]
+fn test_body_collision_before_head_to_head() {
+    //A turns back into its own body where we also move. we're longer, but A's body is there too
+    let outcome = replay(GameMode::Standard, "
+    |  |  |A4|  |  |
+    |  |A0|A3|Y0|Y1|
+    |  |A1|A2|  |Y2|
+    |  |  |  |Y4|Y3|
+    |  |  |  |Y5|  |
+    ", &[("Y", Left), ("A", Right)]);
+    assert_eq!(outcome.eliminated.get("A"), Some(&CauseOfDeath::SelfCollision));
+    assert_eq!(outcome.eliminated.get("Y"), Some(&CauseOfDeath::OtherCollision));
+}
+
+#[test]
+fn test_eliminated_snakes_dont_collide() {
+    //A starves before collisions are checked, so its body is already gone
+    let outcome = replay(GameMode::Standard, "
+    |  |  |  |
+    |Y0|A1|A0|
+    |Y1|A2|  |
+    +A1
+    ", &[("Y", Right), ("A", Up)]);
+    assert_eq!(outcome.eliminated.get("A"), Some(&CauseOfDeath::Starved));
+    assert!(!outcome.eliminated.contains_key("Y"));
+
+    //likewise for leaving the board
+    let outcome = replay(GameMode::Standard, "
+    |  |A0|  |
+    |Y0|A1|  |
+    |Y1|A2|  |
+    ", &[("Y", Right), ("A", Up)]);
+    assert_eq!(outcome.eliminated.get("A"), Some(&CauseOfDeath::OutOfBounds));
+    assert_snake(&outcome, "Y", 99, &[(1, 1), (0, 1)]);
+}
+
+#[test]
+fn test_health() {
+    //food is eaten before starvation is checked
+    let outcome = replay(GameMode::Standard, "
+    |()|Y0|
+    |  |Y1|
+    +Y1
+    ", &[("Y", Left)]);
+    assert_snake(&outcome, "Y", 100, &[(0, 0), (1, 0), (1, 0)]);
+
+    let outcome = replay(GameMode::Standard, "
+    |  |Y0|
+    |  |Y1|
+    +Y1
+    ", &[("Y", Left)]);
+    assert_eq!(outcome.eliminated.get("Y"), Some(&CauseOfDeath::Starved));
+
+    //hazard damage comes after the usual health loss, and can eliminate too
+    let outcome = replay(GameMode::Royale, "
+    |~ |Y0|
+    |  |Y1|
+    +Y20
+    ", &[("Y", Left)]);
+    assert_snake(&outcome, "Y", 20 - 1 - 14, &[(0, 0), (1, 0)]);
+
+    let outcome = replay(GameMode::Royale, "
+    |~ |Y0|
+    |  |Y1|
+    +Y15
+    ", &[("Y", Left)]);
+    assert_eq!(outcome.eliminated.get("Y"), Some(&CauseOfDeath::Hazard));
+}
+
+//one turn as an engine ran it: the state it sent, every snake's move, and the board it sent next.
+//fixtures are JSON arrays of these in the parity directory, and every one found there is replayed
+#[derive(Deserialize)]
+struct Transition {
+    //where the transition came from, e.g. the engine's test case or the game it was recorded in
+    source: String,
+    before: ApiGameState,
+    //in the engine's terms, where up is towards higher y
+    moves: HashMap<ApiSnakeId, ApiDirection>,
+    after: ApiBoard,
+    //the engine's elimination causes, by snake id
+    #[serde(default)]
+    eliminated: HashMap<ApiSnakeId, String>,
+}
+
+//the engine's name for each cause. it doesn't tell hazards apart from other ways of running out of health
+fn engine_cause(cause: CauseOfDeath) -> &'static str {
+    match cause {
+        CauseOfDeath::HeadToHead => "head-collision",
+        CauseOfDeath::OtherCollision => "snake-collision",
+        CauseOfDeath::SelfCollision => "snake-self-collision",
+        CauseOfDeath::OutOfBounds => "wall-collision",
+        CauseOfDeath::Starved | CauseOfDeath::Hazard => "out-of-health",
+        CauseOfDeath::SquadEliminated => "squad-eliminated",
+    }
+}
+
+//the engine's y axis points the other way to ours, which only swaps up and down
+fn engine_move(dir: ApiDirection) -> ApiDirection {
+    match dir {
+        Up => Down,
+        Down => Up,
+        dir => dir,
+    }
+}
+
+fn check_transition(transition: &Transition) {
+    let before = &transition.before;
+    let ids = Board::api_snakes(before).map(|s| s.id.clone()).collect::<Vec<_>>();
+    let mut board = Board::from_api(before);
+    let snake_moves = ids.iter().map(|id| engine_move(transition.moves[id])).collect::<Vec<_>>();
+    let eliminations = board.advance(None, &snake_moves);
+
+    let eliminated = eliminations.iter()
+        .map(|(&snake_index, elimination)| (ids[snake_index].clone(), String::from(engine_cause(elimination.cause))))
+        .collect::<HashMap<_, _>>();
+    assert_eq!(eliminated, transition.eliminated, "{}", transition.source);
+    let survivors = ids.iter().enumerate().filter(|(i, _)| !eliminations.contains_key(i)).map(|(_, id)| id);
+    let snakes = survivors.zip(board.snakes.iter()).collect::<HashMap<_, _>>();
+    assert_eq!(snakes.len(), transition.after.snakes.len(), "{}", transition.source);
+    for expected in transition.after.snakes.iter() {
+        let snake = snakes.get(&expected.id).unwrap_or_else(|| panic!("{}: {} was eliminated", transition.source, expected.id));
+        assert_eq!(snake.health as u32, expected.health, "{}: {}", transition.source, expected.id);
+        assert_eq!(snake.body.nodes, expected.body.iter().map(Coord::from).collect::<Vec<_>>(), "{}: {}", transition.source, expected.id);
+    }
+    //searches don't spawn food, so the engine can only have more
+    let food = transition.after.food.iter().map(Coord::from).collect::<Vec<_>>();
+    assert!(board.food.iter().all(|coord| food.contains(coord)), "{}", transition.source);
+}
+
+#[test]
+fn test_fixtures() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/src/game/parity");
+    let mut n_transitions = 0;
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().map(|ext| ext == "json").unwrap_or(false) {
+            let transitions: Vec<Transition> = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+            for transition in transitions.iter() {
+                check_transition(transition);
+            }
+            n_transitions += transitions.len();
+        }
+    }
+    assert!(n_transitions > 0);
+}
@@ -1,5 +1,8 @@
+use std::collections::VecDeque;
 use crate::api::{ApiCoords};
-use super::coord::{Coord, UnitAbs};
+use super::coord::Coord;
+#[cfg(test)]
+use super::coord::UnitAbs;
 use super::offset::{Offset};
 
 // A path connects a series of coordinates with direction.
@@ -7,21 +10,17 @@ use super::offset::{Offset};
 // the path between them is an undefined shortest manhattan path.
 #[derive(Clone, PartialEq, Debug)]
 pub struct Path {
-    pub nodes: Vec<Coord>,
+    pub nodes: VecDeque<Coord>, //pushing and popping at either end is O(1)
 }
 
 impl Path {
 
-    pub fn new() -> Path {
-        Path {nodes: Vec::new()}
-    }
-
     pub fn from_slice(nodes: &[Coord]) -> Path {
-        Path {nodes: Vec::from(nodes)}
+        Path {nodes: nodes.iter().copied().collect()}
     }
 
     pub fn from_vec(nodes: Vec<Coord>) -> Path {
-        Path {nodes}
+        Path {nodes: VecDeque::from(nodes)}
     }
 
     pub fn from_api(coords: &[ApiCoords]) -> Path {
@@ -34,8 +33,8 @@ impl Path {
         if self.nodes.is_empty() {
             None
         } else {
-            let Coord {x: mut min_x, y: mut min_y} = *self.nodes.first().unwrap();
-            let Coord {x: mut max_x, y: mut max_y} = *self.nodes.first().unwrap();
+            let Coord {x: mut min_x, y: mut min_y} = *self.nodes.front().unwrap();
+            let Coord {x: mut max_x, y: mut max_y} = *self.nodes.front().unwrap();
             for &Coord {x, y} in self.nodes.iter().skip(1) {
                 if x < min_x {
                     min_x = x;
@@ -59,6 +58,7 @@ impl Path {
         self.pop_end();
     }
 
+    #[cfg(test)]
     pub fn slide_end(&mut self, offset: Offset) {
         self.extend_end(offset);
         self.pop_start();
@@ -66,35 +66,32 @@ impl Path {
 
     pub fn extend_start(&mut self, offset: Offset) {
         if !self.nodes.is_empty() {
-            let curr_start = self.nodes.first().unwrap();
+            let curr_start = self.nodes.front().unwrap();
             let new_start = *curr_start + offset;
-            self.nodes.insert(0, new_start);
+            self.nodes.push_front(new_start);
         }
     }
 
     pub fn extend_end(&mut self, offset: Offset) {
         if !self.nodes.is_empty() {
-            let curr_end = self.nodes.last().unwrap();
+            let curr_end = self.nodes.back().unwrap();
             let new_end = *curr_end + offset;
-            self.nodes.push(new_end);
+            self.nodes.push_back(new_end);
         }
     }
 
     pub fn pop_start(&mut self) -> Option<Coord> {
-        if self.nodes.is_empty() {
-            None
-        } else {
-            Some(self.nodes.remove(0))
-        }
+        self.nodes.pop_front()
     }
 
     pub fn pop_end(&mut self) -> Option<Coord> {
-        self.nodes.pop()
+        self.nodes.pop_back()
     }
 
+    #[cfg(test)]
     pub fn dist(&self) -> UnitAbs {
-        self.nodes.windows(2).fold(0, |total, pair| {
-            total + (pair[1] - pair[0]).manhattan_dist()
+        self.nodes.iter().zip(self.nodes.iter().skip(1)).fold(0, |total, (&a, &b)| {
+            total + (b - a).manhattan_dist()
         })
     }
 
@@ -104,11 +101,11 @@ impl Path {
     }
 
     pub fn start(&self) -> Option<Coord> {
-        self.nodes.first().cloned()
+        self.nodes.front().cloned()
     }
 
     pub fn end(&self) -> Option<Coord> {
-        self.nodes.last().cloned()
+        self.nodes.back().cloned()
     }
 
     pub fn get_node(&self, index: usize) -> Option<Coord> {
@@ -0,0 +1,649 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::iter::FromIterator;
+use rand::prelude::*;
+use crate::api::{ApiDirection, ApiRulesetSettings};
+use crate::util::cartesian_product;
+use super::board::{Board, CauseOfDeath, Elimination, Eliminations, SNAKE_MAX_HEALTH};
+use super::coord::{Coord, Unit};
+use super::path::Path;
+use super::snake::{Snake, Health, SquadId};
+use super::offset::ZERO as ZERO_OFFSET;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum GameMode {
+    Standard,
+    Solo,
+    Royale,
+    Constrictor,
+    Wrapped,
+    Squad,
+}
+
+pub const ALL_MODES: [GameMode; 6] = [GameMode::Standard, GameMode::Solo, GameMode::Royale, GameMode::Constrictor, GameMode::Wrapped, GameMode::Squad];
+
+impl GameMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Standard => "standard",
+            Self::Solo => "solo",
+            Self::Royale => "royale",
+            Self::Constrictor => "constrictor",
+            Self::Wrapped => "wrapped",
+            Self::Squad => "squad",
+        }
+    }
+
+    pub fn ruleset(self) -> &'static dyn Ruleset {
+        match self {
+            Self::Standard => &StandardRuleset,
+            Self::Solo => &SoloRuleset,
+            Self::Royale => &RoyaleRuleset,
+            Self::Constrictor => &ConstrictorRuleset,
+            Self::Wrapped => &WrappedRuleset,
+            Self::Squad => &SquadRuleset,
+        }
+    }
+}
+
+impl FromStr for GameMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ALL_MODES.iter()
+            .find(|mode| mode.name() == s)
+            .copied()
+            .ok_or_else(|| format!("Unknown ruleset: {}", s))
+    }
+}
+
+impl fmt::Display for GameMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+//ruleset parameters which may be tuned per game
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Settings {
+    pub food_spawn_chance: u32, //of 100
+    pub minimum_food: u32,
+    pub hazard_damage: Health,
+    pub shrink_every_n_turns: u32,
+    pub allow_body_collisions: bool,
+    pub shared_elimination: bool,
+    pub shared_health: bool,
+    pub shared_length: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings::from(&ApiRulesetSettings::default())
+    }
+}
+
+impl From<&ApiRulesetSettings> for Settings {
+    fn from(settings: &ApiRulesetSettings) -> Self {
+        Settings {
+            food_spawn_chance: settings.food_spawn_chance.min(100),
+            minimum_food: settings.minimum_food,
+            hazard_damage: settings.hazard_damage_per_turn.min(Health::MAX as u32) as Health,
+            shrink_every_n_turns: settings.royale.shrink_every_n_turns,
+            allow_body_collisions: settings.squad.allow_body_collisions,
+            shared_elimination: settings.squad.shared_elimination,
+            shared_health: settings.squad.shared_health,
+            shared_length: settings.squad.shared_length,
+        }
+    }
+}
+
+//the stages of a turn, applied in order by `Board::advance`: move, damage, feed, spawn food, then eliminate.
+//this is the official engine's order. defaults are the standard rules
+pub trait Ruleset: Sync {
+    #[cfg(test)]
+    fn mode(&self) -> GameMode;
+
+    //moves every snake's head in its chosen direction and drops its tail, costing 1 health
+    fn move_snakes(&self, board: &mut Board, snake_moves: &[ApiDirection]) {
+        for snake_index in 0..board.snakes.len() {
+            let dir = snake_moves.get(snake_index).cloned().unwrap_or_else(|| board.snakes[snake_index].get_default_move());
+            board.snakes[snake_index].slither(dir);
+        }
+    }
+
+    //snakes in hazards lose extra health, unless they're about to eat. returns the snakes it took the last health of
+    fn damage_snakes(&self, board: &mut Board) -> Vec<usize> {
+        let mut starved = Vec::new();
+        if board.hazards.is_empty() {
+            return starved;
+        }
+        let damage = board.settings().hazard_damage;
+        for snake_index in 0..board.snakes.len() {
+            let head = board.snakes[snake_index].head();
+            if board.is_hazard(head) && board.find_food(head).is_none() {
+                let snake = &mut board.snakes[snake_index];
+                if !snake.starved() && snake.health <= damage {
+                    starved.push(snake_index);
+                }
+                snake.health = snake.health.saturating_sub(damage);
+            }
+        }
+        starved
+    }
+
+    //all snakes get a chance to eat fairly before food is removed
+    fn feed_snakes(&self, board: &mut Board) {
+        let mut eats = false;
+        for snake_index in 0..board.snakes.len() {
+            if board.find_food(board.snakes[snake_index].head()).is_some() {
+                board.snakes[snake_index].feed(SNAKE_MAX_HEALTH);
+                eats = true;
+            }
+        }
+        if eats {
+            let snakes = &board.snakes;
+            board.food.retain(|&food| snakes.iter().all(|snake| snake.head() != food));
+        }
+    }
+
+    //finds the snakes to eliminate, which the board then removes. `hazard_starved` are those hazards took the last health of
+    fn eliminate_snakes(&self, board: &Board, hazard_starved: &[usize]) -> Eliminations {
+        find_collisions(board, hazard_starved)
+    }
+
+    //anything that happens once eliminated snakes are gone
+    fn end_turn(&self, _board: &mut Board) {}
+
+    //tops food up to the minimum, otherwise there's a chance of one more
+    fn spawn_food(&self, board: &mut Board, rng: &mut dyn RngCore) {
+        let settings = board.settings();
+        let num_food = if (board.food.len() as u32) < settings.minimum_food {
+            settings.minimum_food as usize - board.food.len()
+        } else if settings.food_spawn_chance > 0 && rng.gen_range(0, 100) < settings.food_spawn_chance {
+            1
+        } else {
+            0
+        };
+        if num_food == 0 {
+            return;
+        }
+        let free_spaces: Vec<Coord> = Vec::from_iter(
+            cartesian_product(&[
+                (0..board.width() as Unit).collect(),
+                (0..board.height() as Unit).collect()
+            ]).iter().filter_map(|v| {
+                let coord = Coord::new(v[0], v[1]);
+                if board.find_food(coord).is_some() {
+                    return None;
+                }
+                for snake in board.snakes.iter() {
+                    if snake.find_first_node(coord, 0).is_some() {
+                        return None;
+                    }
+                }
+                Some(coord)
+            })
+        );
+        let new_food: Vec<Coord> = free_spaces.choose_multiple(rng, num_food).cloned().collect();
+        board.food.extend(new_food);
+    }
+
+    //called by the host once per turn, after `advance`, to move hazards
+    fn update_hazards(&self, _board: &mut Board, _turn: u32, _rng: &mut dyn RngCore) {}
+
+    //whether food should be placed when setting up a new board
+    fn initial_food(&self) -> bool {
+        true
+    }
+
+    fn game_over(&self, board: &Board) -> bool {
+        board.snakes.len() <= 1
+    }
+
+    //whether the board's edges connect to the opposite edge
+    fn wraps(&self) -> bool {
+        false
+    }
+
+    //whether snakes grow every turn, so their tails never move out of the way
+    fn always_grows(&self) -> bool {
+        false
+    }
+
+    //whether snakes can move through their allies' bodies
+    fn passes_through_allies(&self, _board: &Board) -> bool {
+        false
+    }
+}
+
+//finds snakes which starved, left the board, or collided, without removing them.
+//like the official rules, snakes eliminated for health or bounds are gone before collisions are checked
+fn find_collisions(board: &Board, hazard_starved: &[usize]) -> Eliminations {
+    let passes_through_allies = board.mode().ruleset().passes_through_allies(board);
+    let eliminated = |cause, by| Elimination {cause, by, turn: board.turn()};
+    let mut dead_snakes = board.snakes.iter().enumerate().filter_map(|(snake_index, snake)| {
+        if snake.starved() {
+            if hazard_starved.contains(&snake_index) {
+                Some((snake_index, eliminated(CauseOfDeath::Hazard, None)))
+            } else {
+                Some((snake_index, eliminated(CauseOfDeath::Starved, None)))
+            }
+        } else if !board.contains(snake.head()) {
+            Some((snake_index, eliminated(CauseOfDeath::OutOfBounds, None)))
+        } else {
+            None
+        }
+    }).collect::<Eliminations>();
+
+    //snakes eliminated by collisions still count for others' collisions, so which are in play is kept apart, as bits by index
+    let live = (0..board.snakes.len())
+        .filter(|snake_index| !dead_snakes.contains_key(snake_index))
+        .fold(0u32, |live, snake_index| live | 1 << snake_index);
+    let live_snakes = || board.snakes.iter().enumerate().filter(|&(snake_index, _)| live & 1 << snake_index != 0);
+    let collide = |snake_index: usize, snake: &Snake| {
+        let head = snake.head();
+        if snake.find_first_node(head, 1).is_some() {
+            return Some(eliminated(CauseOfDeath::SelfCollision, Some(snake_index)));
+        }
+        //a body collision takes precedence, even if the other snake's head is there too
+        for (other_snake_index, other_snake) in live_snakes() {
+            if other_snake_index != snake_index && other_snake.find_first_node(head, 1).is_some() &&
+                !(passes_through_allies && board.allied(snake_index, other_snake_index)) {
+                return Some(eliminated(CauseOfDeath::OtherCollision, Some(other_snake_index)));
+            }
+        }
+        for (other_snake_index, other_snake) in live_snakes() {
+            if other_snake_index != snake_index && other_snake.head() == head && snake.size() <= other_snake.size() {
+                //TWO SNAKES ENTER, ONE SNAKE LEAVES (Ok, actually neither may leave)
+                return Some(eliminated(CauseOfDeath::HeadToHead, Some(other_snake_index)));
+            }
+        }
+        None
+    };
+    for (snake_index, snake) in live_snakes() {
+        if let Some(elimination) = collide(snake_index, snake) {
+            dead_snakes.insert(snake_index, elimination);
+        }
+    }
+    dead_snakes
+}
+
+pub struct StandardRuleset;
+
+impl Ruleset for StandardRuleset {
+    #[cfg(test)]
+    fn mode(&self) -> GameMode {
+        GameMode::Standard
+    }
+}
+
+//a single snake plays until it dies
+pub struct SoloRuleset;
+
+impl Ruleset for SoloRuleset {
+    #[cfg(test)]
+    fn mode(&self) -> GameMode {
+        GameMode::Solo
+    }
+
+    fn game_over(&self, board: &Board) -> bool {
+        board.snakes.is_empty()
+    }
+}
+
+//standard rules played within a shrinking safe zone
+pub struct RoyaleRuleset;
+
+impl Ruleset for RoyaleRuleset {
+    #[cfg(test)]
+    fn mode(&self) -> GameMode {
+        GameMode::Royale
+    }
+
+    //every N turns, a random side of the safe zone is given up to hazards
+    fn update_hazards(&self, board: &mut Board, turn: u32, rng: &mut dyn RngCore) {
+        let every_n_turns = board.settings().shrink_every_n_turns;
+        if every_n_turns == 0 || turn == 0 || !turn.is_multiple_of(every_n_turns) {
+            return;
+        }
+
+        let all_spaces = cartesian_product(&[
+            (0..board.width() as Unit).collect(),
+            (0..board.height() as Unit).collect()
+        ]).iter().map(|v| Coord::new(v[0], v[1])).collect::<Vec<_>>();
+        let safe_spaces = all_spaces.iter().copied().filter(|&coord| !board.is_hazard(coord)).collect::<Vec<_>>();
+        if let Some((mut min, mut max)) = Path::from_vec(safe_spaces).get_bounds() {
+            match rng.gen_range(0, 4) {
+                0 => min.x += 1,
+                1 => max.x -= 1,
+                2 => min.y += 1,
+                _ => max.y -= 1,
+            }
+            board.hazards = all_spaces.into_iter()
+                .filter(|coord| coord.x < min.x || coord.x > max.x || coord.y < min.y || coord.y > max.y)
+                .collect();
+            board.rehash();
+        }
+    }
+}
+
+//no food; every snake grows every turn and never starves
+pub struct ConstrictorRuleset;
+
+impl Ruleset for ConstrictorRuleset {
+    #[cfg(test)]
+    fn mode(&self) -> GameMode {
+        GameMode::Constrictor
+    }
+
+    fn feed_snakes(&self, board: &mut Board) {
+        board.food.clear();
+        for snake in board.snakes.iter_mut() {
+            snake.feed(SNAKE_MAX_HEALTH);
+        }
+    }
+
+    fn spawn_food(&self, _board: &mut Board, _rng: &mut dyn RngCore) {}
+
+    fn initial_food(&self) -> bool {
+        false
+    }
+
+    fn always_grows(&self) -> bool {
+        true
+    }
+}
+
+//moving off an edge of the board enters from the opposite edge
+pub struct WrappedRuleset;
+
+impl Ruleset for WrappedRuleset {
+    #[cfg(test)]
+    fn mode(&self) -> GameMode {
+        GameMode::Wrapped
+    }
+
+    fn move_snakes(&self, board: &mut Board, snake_moves: &[ApiDirection]) {
+        StandardRuleset.move_snakes(board, snake_moves);
+        let bound = board.bound();
+        for snake in board.snakes.iter_mut() {
+            if let Some(head) = snake.body.nodes.front_mut() {
+                *head = head.wrap(bound);
+            }
+        }
+    }
+
+    fn wraps(&self) -> bool {
+        true
+    }
+}
+
+//teams of snakes which win together, and may share their fate, health and length
+pub struct SquadRuleset;
+
+impl Ruleset for SquadRuleset {
+    #[cfg(test)]
+    fn mode(&self) -> GameMode {
+        GameMode::Squad
+    }
+
+    fn eliminate_snakes(&self, board: &Board, hazard_starved: &[usize]) -> Eliminations {
+        let mut dead_snakes = find_collisions(board, hazard_starved);
+        if board.settings().shared_elimination {
+            let fallen_squads: HashSet<SquadId> = dead_snakes.keys()
+                .filter_map(|&snake_index| board.snakes[snake_index].squad)
+                .collect();
+            for (snake_index, snake) in board.snakes.iter().enumerate() {
+                if snake.squad.map(|squad| fallen_squads.contains(&squad)).unwrap_or(false) {
+                    dead_snakes.entry(snake_index).or_insert(Elimination {
+                        cause: CauseOfDeath::SquadEliminated,
+                        by: None,
+                        turn: board.turn(),
+                    });
+                }
+            }
+        }
+        dead_snakes
+    }
+
+    //survivors take on the best health and length in their squad
+    fn end_turn(&self, board: &mut Board) {
+        let settings = board.settings();
+        if settings.shared_health || settings.shared_length {
+            let mut best: HashMap<SquadId, (Health, usize)> = HashMap::new();
+            for snake in board.snakes.iter() {
+                if let Some(squad) = snake.squad {
+                    let entry = best.entry(squad).or_insert((0, 0));
+                    *entry = (entry.0.max(snake.health), entry.1.max(snake.size()));
+                }
+            }
+            for snake in board.snakes.iter_mut() {
+                if let Some(&(health, size)) = snake.squad.and_then(|squad| best.get(&squad)) {
+                    if settings.shared_health {
+                        snake.health = health;
+                    }
+                    if settings.shared_length {
+                        while snake.size() < size {
+                            snake.body.extend_end(ZERO_OFFSET);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    //the game ends once only one squad remains
+    fn game_over(&self, board: &Board) -> bool {
+        board.snakes.iter().skip(1).all(|snake| snake.squad.is_some() && snake.squad == board.snakes[0].squad)
+    }
+
+    fn passes_through_allies(&self, board: &Board) -> bool {
+        board.settings().allow_body_collisions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::ApiGameState;
+    use crate::api::ApiDirection::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    fn board(mode: GameMode, s: &str) -> Board {
+        let mut board = Board::from_api(&ApiGameState::parse_basic(s));
+        board.set_mode(mode);
+        board
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        for mode in ALL_MODES.iter() {
+            assert_eq!(mode.name().parse::<GameMode>(), Ok(*mode));
+            assert_eq!(mode.ruleset().mode(), *mode);
+        }
+        assert!("squid".parse::<GameMode>().is_err());
+    }
+
+    #[test]
+    fn test_solo_game_over() {
+        let standard = board(GameMode::Standard, "
+        |  |  |  |
+        |  |Y0|  |
+        |  |Y1|  |
+        ");
+        let solo = board(GameMode::Solo, "
+        |  |  |  |
+        |  |Y0|  |
+        |  |Y1|  |
+        ");
+        assert!(standard.game_over());
+        assert!(!solo.game_over());
+    }
+
+    #[test]
+    fn test_constrictor_growth() {
+        let mut board = board(GameMode::Constrictor, "
+        |  |()|  |
+        |  |Y0|  |
+        |  |Y1|  |
+        +Y50
+        ");
+        board.advance(Some(&mut rand::thread_rng()), &[Right]);
+        assert_eq!(board.you().size(), 3);
+        assert_eq!(board.you().health, SNAKE_MAX_HEALTH);
+        assert!(board.food.is_empty());
+    }
+
+    #[test]
+    fn test_spawn_food() {
+        let mut board = board(GameMode::Standard, "
+        |  |  |  |  |
+        |  |Y0|  |  |
+        |  |Y1|  |  |
+        ");
+        //seeded so no food spawns where we're about to move
+        let mut rng = StdRng::seed_from_u64(1);
+        board.set_settings(Settings {food_spawn_chance: 0, minimum_food: 3, ..Settings::default()});
+        board.advance(Some(&mut rng), &[Up]);
+        assert_eq!(board.food.len(), 3);
+        assert!(board.food.iter().all(|&food| board.you().find_first_node(food, 0).is_none()));
+        board.advance(Some(&mut rng), &[Right]);
+        assert_eq!(board.food.len(), 3);
+
+        board.set_settings(Settings {food_spawn_chance: 100, minimum_food: 0, ..Settings::default()});
+        board.advance(Some(&mut rng), &[Down]);
+        assert_eq!(board.food.len(), 4);
+    }
+
+    #[test]
+    fn test_squads() {
+        let squad = "
+        |  |A3|  |  |  |
+        |Y0|A2|A1|A0|  |
+        |Y1|  |  |B0|  |
+        |Y2|  |  |B1|  |
+        |  |  |  |B2|B3|
+        =Y1
+        =A1
+        =B2
+        +Y50
+        +A80
+        ";
+        //enemy order isn't fixed, so moves are given by head
+        let moves = |board: &Board, you: ApiDirection, a: ApiDirection, b: ApiDirection| {
+            board.snakes.iter().map(|snake| match snake.head() {
+                head if head == Coord::new(3, 1) => a,
+                head if head == Coord::new(3, 2) => b,
+                _ => you,
+            }).collect::<Vec<_>>()
+        };
+        let mut squads = board(GameMode::Squad, squad);
+        let a = squads.snakes.iter().position(|snake| snake.head() == Coord::new(3, 1)).unwrap();
+        let b = 3 - a;
+        assert!(squads.allied(0, a));
+        assert!(!squads.allied(0, b));
+        assert!(!squads.allied(0, 0));
+        assert!(squads.enumerate_snake_moves(&squads.bits())[0].contains(&Right));
+
+        //we pass through our ally and share its health and length
+        let dead = squads.advance(None, &moves(&squads, Right, Up, Left));
+        assert!(dead.is_empty());
+        assert_eq!(squads.snakes[0].health, 79);
+        assert_eq!(squads.snakes[a].health, 79);
+        assert_eq!(squads.snakes[0].size(), 4);
+        assert!(!squads.game_over());
+
+        //A runs into B, so we're eliminated with it
+        let mut collided = board(GameMode::Squad, squad);
+        let a = collided.snakes.iter().position(|snake| snake.head() == Coord::new(3, 1)).unwrap();
+        let dead = collided.advance(None, &moves(&collided, Up, Down, Left));
+        assert_eq!(dead.get(&a), Some(&Elimination {cause: CauseOfDeath::OtherCollision, by: Some(3 - a), turn: 1}));
+        assert_eq!(dead.get(&0).map(|e| e.cause), Some(CauseOfDeath::SquadEliminated));
+        assert!(collided.game_over());
+
+        //without squad rules, moving into an ally is a collision
+        let mut standard = board(GameMode::Standard, squad);
+        let dead = standard.advance(None, &moves(&standard, Right, Up, Left));
+        assert_eq!(dead.get(&0).map(|e| e.cause), Some(CauseOfDeath::OtherCollision));
+    }
+
+    #[test]
+    fn test_hazard_damage() {
+        let mut damaged = board(GameMode::Royale, "
+        |  |~ |~ |
+        |  |Y0|~ |
+        |  |Y1|()~|
+        |  |Y2|  |
+        |B0|B1|  |
+        +Y50
+        +B50
+        ");
+        damaged.advance(None, &[Up, Up]);
+        assert_eq!(damaged.you().health, 50 - 1 - damaged.settings().hazard_damage);
+        assert_eq!(damaged.snakes[1].health, 49);
+
+        //eating food in a hazard avoids the damage
+        let mut fed = board(GameMode::Royale, "
+        |  |  |  |
+        |Y0|()~|  |
+        |Y1|  |  |
+        +Y50
+        ");
+        fed.advance(None, &[Right]);
+        assert_eq!(fed.you().health, SNAKE_MAX_HEALTH);
+
+        //hazards can starve a snake
+        let mut starving = board(GameMode::Standard, "
+        |~ |~ |
+        |Y0|Y1|
+        +Y10
+        ");
+        let dead = starving.advance(None, &[Up]);
+        assert_eq!(dead.get(&0).map(|e| e.cause), Some(CauseOfDeath::Hazard));
+
+        //but not if the usual health loss would have anyway
+        let mut starving = board(GameMode::Standard, "
+        |~ |~ |
+        |Y0|Y1|
+        +Y1
+        ");
+        let dead = starving.advance(None, &[Up]);
+        assert_eq!(dead.get(&0).map(|e| e.cause), Some(CauseOfDeath::Starved));
+    }
+
+    #[test]
+    fn test_royale_shrink() {
+        let mut board = board(GameMode::Royale, "
+        |  |  |  |  |
+        |  |Y0|  |  |
+        |  |Y1|  |  |
+        |  |  |  |  |
+        ");
+        let every_n_turns = board.settings().shrink_every_n_turns;
+        RoyaleRuleset.update_hazards(&mut board, every_n_turns - 1, &mut rand::thread_rng());
+        assert!(board.hazards.is_empty());
+
+        RoyaleRuleset.update_hazards(&mut board, every_n_turns, &mut rand::thread_rng());
+        assert_eq!(board.hazards.len(), 4);
+        RoyaleRuleset.update_hazards(&mut board, every_n_turns * 2, &mut rand::thread_rng());
+        assert!(board.hazards.len() == 7 || board.hazards.len() == 8);
+        for turn in 3..=8 {
+            RoyaleRuleset.update_hazards(&mut board, every_n_turns * turn, &mut rand::thread_rng());
+        }
+        assert_eq!(board.hazards.len(), 16);
+    }
+
+    #[test]
+    fn test_wrapped_edges() {
+        let mut board = board(GameMode::Wrapped, "
+        |  |Y0|  |
+        |  |Y1|  |
+        |  |  |  |
+        ");
+        let dead = board.advance(None, &[Up]);
+        assert!(dead.is_empty());
+        assert_eq!(board.you().head(), Coord::new(1, 2));
+    }
+}
@@ -6,11 +6,14 @@ use super::coord::Coord;
 use super::offset::ZERO as ZERO_OFFSET;
 
 pub type Health = u8; //make this u16 if there will be health > 256
+pub type SquadId = u8;
 
 #[derive(Clone, PartialEq, Debug)]
 pub struct Snake {
     pub health: Health,
     pub body: Path,
+    //snakes in the same squad are allies. the board assigns these since API squads are strings
+    pub squad: Option<SquadId>,
 }
 
 impl Snake {
@@ -18,7 +21,8 @@ impl Snake {
     pub fn init(health: Health, start: Coord, size: usize) -> Snake {
         Snake {
             health,
-            body: Path::from_vec(iter::repeat(start).take(size).collect()),
+            body: Path::from_vec(iter::repeat_n(start, size).collect()),
+            squad: None,
         }
     }
 
@@ -30,13 +34,15 @@ impl Snake {
         Ok(Snake {
             health: api_snake.health as Health,
             body,
+            squad: None,
         })
     }
 
     //default move according to battlesnake rules, used when a snake doesn't reply in time
     pub fn get_default_move(&self) -> ApiDirection {
         if let Some(neck) = self.neck() {
-            if let Ok(dir) = ApiDirection::try_from(self.head() - neck) {
+            //neck may be on the opposite edge of a wrapped board
+            if let Ok(dir) = ApiDirection::try_from((self.head() - neck).unwrap_neighbour()) {
                 return dir;
             }
         }
@@ -70,6 +76,8 @@ impl Snake {
         self.body.nodes.iter().skip(offset).position(|node| loc == *node)
     }
 
+    //like the official engine, growing copies the tail right away. the copy keeps the tail in place for one move,
+    //which is when the snake visibly grows
     pub fn feed(&mut self, new_health: Health) {
         self.health = new_health;
         self.body.extend_end(ZERO_OFFSET);
@@ -101,6 +109,7 @@ mod tests {
                 ApiCoords {x: 1, y: 0},
                 ApiCoords {x: 2, y: 0},
             ],
+            ..Default::default()
         });
         assert!(snake.is_ok());
 
@@ -109,6 +118,7 @@ mod tests {
             name: String::from("waylon slithers"),
             health: 80,
             body: vec![],
+            ..Default::default()
         });
         assert!(snake.is_err());
     }
@@ -124,6 +134,7 @@ mod tests {
                 ApiCoords {x: 2, y: 0},
                 ApiCoords {x: 2, y: 1},
             ],
+            ..Default::default()
         }).unwrap();
 
         assert_eq!(snake.head(), Coord::new(1, 0));
@@ -131,6 +142,19 @@ mod tests {
         assert_eq!(snake.tail(), Coord::new(2, 1));
         assert_eq!(snake.get_default_move(), ApiDirection::Left);
 
+        //wrapped around the left edge of the board
+        let snake = Snake::from_api(&ApiSnake {
+            id: String::from("123abc"),
+            name: String::from("waylon slithers"),
+            health: 80,
+            body: vec![
+                ApiCoords {x: 10, y: 0},
+                ApiCoords {x: 0, y: 0},
+            ],
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(snake.get_default_move(), ApiDirection::Left);
+
         //unusual case, but should still work...
         let snake = Snake::from_api(&ApiSnake {
             id: String::from("123abc"),
@@ -139,6 +163,7 @@ mod tests {
             body: vec![
                 ApiCoords {x: 1, y: 0},
             ],
+            ..Default::default()
         }).unwrap();
 
         assert_eq!(snake.head(), Coord::new(1, 0));
@@ -0,0 +1,124 @@
+use std::iter;
+use super::coord::Coord;
+use super::snake::{Snake, Health};
+
+#[derive(Copy, Clone)]
+enum Feature {
+    Segment = 1,
+    Head,
+    Size,
+    Health,
+    Food,
+    Hazard,
+    Turn,
+}
+
+//splitmix64 finalizer; stands in for a table of random keys
+#[inline]
+fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[inline]
+fn key(feature: Feature, snake_index: usize, value: u64) -> u64 {
+    mix(((feature as u64) << 56) | ((snake_index as u64 & 0xff) << 48) | (value & 0xffff_ffff_ffff))
+}
+
+#[inline]
+fn coord_value(coord: Coord) -> u64 {
+    ((coord.x as u8 as u64) << 8) | coord.y as u8 as u64
+}
+
+//a body node and the one after it, which is the direction the body runs in from there. keying nodes on their own would
+//lose the body's order, so bodies through the same cells with different tails would hash the same. the tail has no next
+#[inline]
+fn segment_key(snake_index: usize, coord: Coord, next: Option<Coord>) -> u64 {
+    key(Feature::Segment, snake_index, (coord_value(coord) << 17) | next.map(coord_value).unwrap_or(1 << 16))
+}
+
+#[inline]
+fn head_key(snake_index: usize, coord: Coord) -> u64 {
+    key(Feature::Head, snake_index, coord_value(coord))
+}
+
+#[inline]
+fn size_key(snake_index: usize, size: usize) -> u64 {
+    key(Feature::Size, snake_index, size as u64)
+}
+
+#[inline]
+fn health_key(snake_index: usize, health: Health) -> u64 {
+    key(Feature::Health, snake_index, health as u64)
+}
+
+pub fn snake_hash(snake_index: usize, snake: &Snake) -> u64 {
+    let nodes = &snake.body.nodes;
+    let nexts = nodes.iter().skip(1).map(|&next| Some(next)).chain(iter::once(None));
+    nodes.iter().zip(nexts).fold(
+        head_key(snake_index, snake.head()) ^ size_key(snake_index, snake.size()) ^ health_key(snake_index, snake.health),
+        |hash, (&node, next)| hash ^ segment_key(snake_index, node, next)
+    )
+}
+
+//the change to `snake_hash` after the snake moves its head and tail and maybe grows by stacking its tail.
+//the new head leads to the old one, and the new tail, which led to the old tail, ends the body
+pub fn snake_move_delta(snake_index: usize, snake: &Snake, old_head: Coord, old_tail: Coord, old_size: usize, old_health: Health) -> u64 {
+    let (head, tail) = (snake.head(), snake.tail());
+    let mut delta = segment_key(snake_index, head, Some(old_head))
+        ^ segment_key(snake_index, tail, Some(old_tail)) ^ segment_key(snake_index, old_tail, None)
+        ^ segment_key(snake_index, tail, None)
+        ^ head_key(snake_index, old_head) ^ head_key(snake_index, head);
+    let size = snake.size();
+    if size != old_size {
+        delta ^= size_key(snake_index, old_size) ^ size_key(snake_index, size);
+        //stacked nodes cancel out in pairs
+        if (size - old_size) % 2 == 1 {
+            delta ^= segment_key(snake_index, tail, Some(tail));
+        }
+    }
+    if old_health != snake.health {
+        delta ^= health_key(snake_index, old_health) ^ health_key(snake_index, snake.health);
+    }
+    delta
+}
+
+#[inline]
+pub fn food_key(coord: Coord) -> u64 {
+    key(Feature::Food, 0, coord_value(coord))
+}
+
+pub fn food_hash(food: &[Coord]) -> u64 {
+    food.iter().fold(0, |hash, &coord| hash ^ food_key(coord))
+}
+
+pub fn hazard_hash(hazards: &[Coord]) -> u64 {
+    hazards.iter().fold(0, |hash, &coord| hash ^ key(Feature::Hazard, 0, coord_value(coord)))
+}
+
+#[inline]
+pub fn turn_key(turn: u32) -> u64 {
+    key(Feature::Turn, 0, turn as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::path::Path;
+    use super::super::coord::Unit;
+
+    #[test]
+    fn test_body_order() {
+        let snake = |body: &[(Unit, Unit)]| Snake {
+            health: 100,
+            body: Path::from_vec(body.iter().map(|&(x, y)| Coord::new(x, y)).collect()),
+            squad: None,
+        };
+        //the same cells and head, but the tails are at opposite ends
+        let clockwise = snake(&[(1, 1), (2, 1), (2, 2), (1, 2)]);
+        let anticlockwise = snake(&[(1, 1), (1, 2), (2, 2), (2, 1)]);
+        assert_ne!(snake_hash(0, &clockwise), snake_hash(0, &anticlockwise));
+    }
+}
@@ -0,0 +1,366 @@
+use std::cmp::max;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use crate::brain::Score;
+use crate::game::{Board, BoardBits, Coord, GameMode, Territory, Unit, UnitAbs};
+
+//a hazard space is worth this fraction of a safe one when valuing territory
+const HAZARD_AREA_VALUE: Score = 0.25;
+//tail access score when we can't find a way back to our tail; cut off, but not necessarily dead
+const NO_TAIL_ACCESS: Score = 0.5;
+
+//which parts of the heuristic matter depends on the game mode
+struct Profile {
+    //whether we need to reach food before starving
+    food: bool,
+}
+
+const STANDARD_PROFILE: Profile = Profile {food: true};
+//there's no food in constrictor and nobody starves, so only space matters
+const CONSTRICTOR_PROFILE: Profile = Profile {food: false};
+
+fn profile(mode: GameMode) -> &'static Profile {
+    match mode {
+        GameMode::Constrictor => &CONSTRICTOR_PROFILE,
+        _ => &STANDARD_PROFILE,
+    }
+}
+
+//what components get to look at, computed once per board and shared between snakes
+pub struct Context<'a> {
+    pub board: &'a Board,
+    pub bits: BoardBits,
+    pub territories: Vec<Territory>,
+    pub total_area: UnitAbs,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(board: &'a Board) -> Context<'a> {
+        let bits = board.bits();
+        let territories = board.get_territories(&bits);
+        Context {
+            board,
+            bits,
+            total_area: max(1, territories.iter().map(|terr| terr.area).sum()),
+            territories,
+        }
+    }
+}
+
+//one named part of the heuristic, scoring a board for a snake from 0 (bad) to 1 (good)
+pub trait Heuristic: Sync {
+    fn name(&self) -> &'static str;
+    fn score(&self, context: &Context, snake_index: usize) -> Score;
+}
+
+//share of the board we can reach first, with hazards counting for less
+struct TerritoryControl;
+
+impl Heuristic for TerritoryControl {
+    fn name(&self) -> &'static str {
+        "territory"
+    }
+
+    fn score(&self, context: &Context, snake_index: usize) -> Score {
+        let territory = &context.territories[snake_index];
+        (territory.area as Score - territory.hazard_area as Score * (1.0 - HAZARD_AREA_VALUE)) / context.total_area as Score
+    }
+}
+
+//whether we can reach food, or expect it to spawn, before starving
+struct FoodUrgency;
+
+impl Heuristic for FoodUrgency {
+    fn name(&self) -> &'static str {
+        "food"
+    }
+
+    fn score(&self, context: &Context, snake_index: usize) -> Score {
+        let board = context.board;
+        if !profile(board.mode()).food {
+            return 1.0;
+        }
+        let turns_until_starve = board.snakes[snake_index].health;
+        if turns_until_starve == 0 {
+            return 0.0;
+        }
+        let urgency = |dist: Score| 1.0 - (dist / turns_until_starve as Score).min(1.0);
+        //searches never spawn food, so this is the only place spawns are accounted for, by their expected value
+        context.territories[snake_index].nearest_food.map(|nearest| urgency(nearest as Score)).unwrap_or_else(|| {
+            let settings = board.settings();
+            if (board.food.len() as u32) < settings.minimum_food {
+                //food is topped up to the minimum straight away, somewhere random. two random spaces are a third of
+                //the width and height apart on average
+                urgency((board.width() + board.height()) as Score / 3.0)
+            } else {
+                //otherwise it only has a chance to spawn, and might not be in our territory
+                (settings.food_spawn_chance as Score / 100.0 *
+                    turns_until_starve as Score *
+                    board.snakes.len() as Score /
+                    context.total_area as Score
+                ).min(1.0)
+            }
+        })
+    }
+}
+
+//fraction of snakes which can't threaten us head-to-head next turn
+struct HeadToHeadDanger;
+
+impl Heuristic for HeadToHeadDanger {
+    fn name(&self) -> &'static str {
+        "head_to_head"
+    }
+
+    fn score(&self, context: &Context, snake_index: usize) -> Score {
+        let board = context.board;
+        let snake = &board.snakes[snake_index];
+        board.snakes.iter().enumerate()
+            .filter(|(other_index, other)| {
+                *other_index == snake_index || //dont need to worry about self
+                other.size() < snake.size()  || //dont need to worry about small snakes
+                board.dist(other.head(), snake.head()) > 2 //dont need to worry about distant snakes
+            })
+            .count() as Score / board.snakes.len() as Score
+    }
+}
+
+//fewer snakes left is better for everyone still alive
+struct SnakesRemaining;
+
+impl Heuristic for SnakesRemaining {
+    fn name(&self) -> &'static str {
+        "snakes"
+    }
+
+    fn score(&self, context: &Context, _snake_index: usize) -> Score {
+        1.0 / context.board.snakes.len() as Score
+    }
+}
+
+//our size against the largest other snake's
+struct LengthAdvantage;
+
+impl Heuristic for LengthAdvantage {
+    fn name(&self) -> &'static str {
+        "length"
+    }
+
+    fn score(&self, context: &Context, snake_index: usize) -> Score {
+        let snakes = &context.board.snakes;
+        let size = snakes[snake_index].size();
+        let largest_other = snakes.iter().enumerate()
+            .filter(|(other_index, _)| *other_index != snake_index)
+            .map(|(_, other)| other.size())
+            .max();
+        largest_other.map(|other| size as Score / (size + other) as Score).unwrap_or(1.0)
+    }
+}
+
+//how little of the board the largest enemy territory covers
+struct EnemyTerritory;
+
+impl Heuristic for EnemyTerritory {
+    fn name(&self) -> &'static str {
+        "enemy_territory"
+    }
+
+    fn score(&self, context: &Context, snake_index: usize) -> Score {
+        let largest_enemy = context.territories.iter().enumerate()
+            .filter(|(other_index, _)| *other_index != snake_index && !context.board.allied(snake_index, *other_index))
+            .map(|(_, territory)| territory.area)
+            .max()
+            .unwrap_or(0);
+        1.0 - largest_enemy as Score / context.total_area as Score
+    }
+}
+
+//closeness of our head to the middle of the board, where there are the most ways out. wrapped boards have no middle
+struct CenterControl;
+
+impl Heuristic for CenterControl {
+    fn name(&self) -> &'static str {
+        "center"
+    }
+
+    fn score(&self, context: &Context, snake_index: usize) -> Score {
+        let board = context.board;
+        if board.wraps() {
+            return 1.0;
+        }
+        let center = Coord::new((board.width() / 2) as Unit, (board.height() / 2) as Unit);
+        let max_dist = max(1, board.width() / 2 + board.height() / 2);
+        1.0 - board.dist(board.snakes[snake_index].head(), center) as Score / max_dist as Score
+    }
+}
+
+//whether we can still follow our own tail, which keeps us from being sealed in
+struct TailAccess;
+
+impl Heuristic for TailAccess {
+    fn name(&self) -> &'static str {
+        "tail"
+    }
+
+    fn score(&self, context: &Context, snake_index: usize) -> Score {
+        let snake = &context.board.snakes[snake_index];
+        if snake.head() == snake.tail() || context.board.pathfind(&context.bits, snake.head(), snake.tail()).is_some() {
+            1.0
+        } else {
+            NO_TAIL_ACCESS
+        }
+    }
+}
+
+pub const N_COMPONENTS: usize = 8;
+
+//every component, in the order their weights are stored
+pub const COMPONENTS: [&dyn Heuristic; N_COMPONENTS] = [
+    &TerritoryControl,
+    &FoodUrgency,
+    &HeadToHeadDanger,
+    &SnakesRemaining,
+    &LengthAdvantage,
+    &EnemyTerritory,
+    &CenterControl,
+    &TailAccess,
+];
+
+//exponent of each component, indexed like `COMPONENTS`. the heuristic is their product, so a weight of 0 skips one entirely
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Weights(pub [Score; N_COMPONENTS]);
+
+const DEFAULT_WEIGHTS: Weights = Weights([1.0, 1.0, 1.0, 2.0, 0.0, 0.0, 0.0, 0.0]);
+
+impl Default for Weights {
+    fn default() -> Self {
+        DEFAULT_WEIGHTS
+    }
+}
+
+impl Weights {
+    #[cfg(test)]
+    pub fn get(&self, name: &str) -> Option<Score> {
+        COMPONENTS.iter().position(|component| component.name() == name).map(|i| self.0[i])
+    }
+
+    //a JSON object of component names to weights. missing components keep their default weight
+    pub fn from_json(json: &str) -> Result<Weights, String> {
+        let named: BTreeMap<String, Score> = serde_json::from_str(json).map_err(|e| format!("Invalid weights: {}", e))?;
+        let mut weights = Weights::default();
+        for (name, weight) in named {
+            let i = COMPONENTS.iter().position(|component| component.name() == name)
+                .ok_or_else(|| format!("Unknown heuristic component: {}", name))?;
+            weights.0[i] = weight;
+        }
+        Ok(weights)
+    }
+
+    pub fn to_json(self) -> String {
+        let named: BTreeMap<&str, Score> = COMPONENTS.iter().map(|component| component.name()).zip(self.0.iter().copied()).collect();
+        serde_json::to_string_pretty(&named).unwrap()
+    }
+
+    pub fn load(path: &str) -> Result<Weights, String> {
+        let json = fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+        Weights::from_json(&json)
+    }
+
+    //should be 1.0 if will win, 0.0 if will lose, in between otherwise
+    pub fn score(&self, board: &Board, snake_index: usize) -> Score {
+        self.score_in(&Context::new(board), snake_index)
+    }
+
+    //every snake's score, sharing one territory calculation
+    pub fn scores(&self, board: &Board) -> Vec<Score> {
+        let context = Context::new(board);
+        (0..board.snakes.len()).map(|snake_index| self.score_in(&context, snake_index)).collect()
+    }
+
+    pub fn score_in(&self, context: &Context, snake_index: usize) -> Score {
+        let board = context.board;
+        //only our squad survives
+        if snake_index == 0 && board.game_over() && (1..board.snakes.len()).all(|i| board.allied(0, i)) {
+            return 1.0;
+        }
+        COMPONENTS.iter().zip(self.0.iter())
+            .filter(|(_, &weight)| weight != 0.0)
+            .map(|(component, &weight)| {
+                let score = component.score(context, snake_index);
+                if weight == 1.0 {score} else {score.powf(weight)}
+            })
+            .product()
+    }
+
+    //a table of each component's score and weighted factor for a snake, to see why a board scored what it did
+    pub fn explain(&self, board: &Board, snake_index: usize) -> String {
+        let context = Context::new(board);
+        let mut table = format!("{:<16} {:>8} {:>8} {:>8}\n", "component", "score", "weight", "factor");
+        for (component, &weight) in COMPONENTS.iter().zip(self.0.iter()) {
+            let score = component.score(&context, snake_index);
+            writeln!(table, "{:<16} {:>8.4} {:>8.2} {:>8.4}", component.name(), score, weight, score.powf(weight)).unwrap();
+        }
+        writeln!(table, "{:<16} {:>8.4}", "total", self.score_in(&context, snake_index)).unwrap();
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::ApiGameState;
+
+    fn board() -> Board {
+        Board::from_api(&ApiGameState::parse_basic("
+        |()|  |  |  |  |  |  |
+        |  |Y0|  |  |  |A0|  |
+        |  |Y1|  |  |  |A1|  |
+        |  |Y2|  |  |  |A2|  |
+        |  |  |  |  |  |A3|  |
+        "))
+    }
+
+    #[test]
+    fn test_default_weights() {
+        //the original hardcoded heuristic: territory * food * head-to-head * snakes ^ 2
+        let board = board();
+        let context = Context::new(&board);
+        for snake_index in 0..board.snakes.len() {
+            let expected = TerritoryControl.score(&context, snake_index) *
+                FoodUrgency.score(&context, snake_index) *
+                HeadToHeadDanger.score(&context, snake_index) *
+                SnakesRemaining.score(&context, snake_index) *
+                SnakesRemaining.score(&context, snake_index);
+            assert!((Weights::default().score(&board, snake_index) - expected).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_components_in_range() {
+        let board = board();
+        let context = Context::new(&board);
+        for component in COMPONENTS.iter() {
+            for snake_index in 0..board.snakes.len() {
+                let score = component.score(&context, snake_index);
+                assert!((0.0..=1.0).contains(&score), "{}={}", component.name(), score);
+            }
+        }
+        //the enemy is longer
+        assert!(LengthAdvantage.score(&context, 0) < LengthAdvantage.score(&context, 1));
+    }
+
+    #[test]
+    fn test_weights_json() {
+        let weights = Weights::from_json(r#"{"length": 0.5, "snakes": 1}"#).unwrap();
+        assert_eq!(weights.get("length"), Some(0.5));
+        assert_eq!(weights.get("snakes"), Some(1.0));
+        assert_eq!(weights.get("territory"), Some(1.0));
+        assert_eq!(Weights::from_json(&weights.to_json()), Ok(weights));
+        assert!(Weights::from_json(r#"{"charisma": 1}"#).is_err());
+
+        let weighted = weights.score(&board(), 0);
+        assert_ne!(weighted, Weights::default().score(&board(), 0));
+        assert!(weights.explain(&board(), 0).contains("length"));
+    }
+}
@@ -1,88 +1,71 @@
-use std::time::Duration;
 use std::io;
+use std::time::Instant;
 use futures::{future, FutureExt};
 use log::*;
-use tokio::time::timeout;
 use uuid::Uuid;
-use hyper::{Client, Request, Body, body, client::connect::HttpConnector};
-use crate::game::{Board, Snake, UnitAbs};
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use crate::game::{Board, Elimination, Eliminations, Snake, UnitAbs, GameMode, Settings};
 use crate::api::*;
+use crate::player::Player;
+use crate::replay::{self, ReplayFrame, ReplayMove, ReplayElimination};
 use crate::util::draw_board;
 
-const START_TIMEOUT_MS: u64 = 5000;
-
 #[derive(Clone)]
 struct LiveSnake {
     pub id: ApiSnakeId,
-    pub addr: String,
-    pub config: ApiSnakeConfig,
+    //index into the game's players
+    pub player: usize,
+    pub name: String,
 }
 
-async fn notify_start(client: &Client<HttpConnector>, addr: &str, game_state: ApiGameState) -> Result<ApiSnakeConfig, String> {
-    let req = Request::post(format!("{}/start", addr))
-        .header("Content-Type", "application/json")
-        .body(Body::from(serde_json::to_string(&game_state).unwrap()))
-        .unwrap();
-    let k = timeout(Duration::from_millis(START_TIMEOUT_MS), client.request(req));
-    match k.await {
-        Err(_) => {
-            Err(format!("Snake @ {} timed out after {} ms", addr, START_TIMEOUT_MS))
-        },
-        Ok(Err(e)) => {
-            Err(format!("Snake @ {} failed to reply: {}", addr, e))
-        },
-        Ok(Ok(res)) => {
-            let res_body = body::to_bytes(res.into_body());
-            match serde_json::from_slice::<ApiSnakeConfig>(&res_body.await.unwrap()) {
-                Ok(start_response) => Ok(start_response),
-                Err(e) => Err(format!("Snake @ {} responded with invalid JSON: {}", addr, e))
-            }
-        }
-    }
+pub struct GameConfig {
+    //how long in milliseconds snakes have to respond
+    pub timeout_ms: u64,
+    pub width: UnitAbs,
+    pub height: UnitAbs,
+    pub mode: GameMode,
+    pub settings: Settings,
+    //starting positions, food, hazards and builtin players' random choices all come from this, so the same seed and moves
+    //replay the same game
+    pub seed: u64,
+    //wait for user input before each turn
+    pub prompt: bool,
+    //file to record every turn to, as line-delimited JSON
+    pub replay: Option<String>,
 }
 
-async fn get_move(client: &Client<HttpConnector>, addr: String, game_state: ApiGameState, timeout_ms: u64) -> Result<ApiMove, String> {
-    let req = Request::post(format!("{}/move", addr))
-        .header("Content-Type", "application/json")
-        .body(Body::from(serde_json::to_string(&game_state).unwrap()))
-        .unwrap();
-    match timeout(Duration::from_millis(timeout_ms), client.request(req)).await {
-        Err(_) => {
-            Err(format!("Snake @ {} timed out after {} ms", addr, timeout_ms))
-        },
-        Ok(Err(e)) => {
-            Err(format!("Snake @ {} failed to reply: {}", addr, e))
-        },
-        Ok(Ok(res)) => {
-            let res_body = body::to_bytes(res.into_body());
-            match serde_json::from_slice::<ApiMove>(&res_body.await.unwrap()) {
-                Ok(move_response) => Ok(move_response),
-                Err(e) => Err(format!("Snake @ {} responded with invalid JSON: {}", addr, e))
-            }
+//plays a game to the end, returning the names of any survivors, or None if it couldn't start
+pub async fn run_game(config: &GameConfig, players: &[Box<dyn Player>]) -> Option<Vec<String>> {
+    let GameConfig {timeout_ms, width, height, mode, settings, seed, prompt, ..} = *config;
+    info!("Initializing {}x{} board with {} rules: {:?}", width, height, mode, settings);
+    info!("Seed: {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut board = Board::init(width, height, players.len(), mode, settings, &mut rng).unwrap();
+    let game_id: ApiGameId = Uuid::new_v4().to_string();
+    let ids = players.iter().map(|_| Uuid::new_v4().to_string()).collect::<Vec<_>>();
+    let names = player_names(players);
+    let identities = ids.iter().zip(names.iter()).map(|(id, name)| (id.as_str(), name.as_str())).collect::<Vec<_>>();
+    if let Some(path) = config.replay.as_ref() {
+        if let Err(e) = replay::create(path) {
+            error!("Couldn't create replay {}: {}", path, e);
         }
     }
-}
-
-pub async fn run_game(timeout_ms: u64, snakes_addrs: &[String], width: UnitAbs, height: UnitAbs, prompt: bool) {
-    info!("Initializing {}x{} board", width, height);
-    let mut board = Board::init(width, height, snakes_addrs.len()).unwrap();
-    let mut turn: u32 = 0;
-    let game_id: ApiGameId = Uuid::new_v4().to_string();
-    let client = Client::default();
 
     info!("Notifying snakes of game start; id: {}", &game_id);
     let live_snakes = future::try_join_all(
         //build an iterator of futures representing results of /start API call
-        snakes_addrs.iter().enumerate().map(|(snake_index, addr)| {
-            let game_state = build_api_game_state(&board, snake_index, turn, &game_id);
-            let addr_copy = addr.clone();
+        players.iter().enumerate().map(|(snake_index, player)| {
+            let game_state = build_api_game_state(&board, snake_index, &game_id, timeout_ms, &identities);
+            let id = ids[snake_index].clone();
+            let name = names[snake_index].clone();
             //within the future, within the result, wrap their response in a LiveSnake
-            notify_start(&client, &addr, game_state).map(move |call_result| {
-                call_result.map(|conf| {
+            player.start(game_state).map(move |call_result| {
+                call_result.map(|_| {
                     LiveSnake {
-                        id: Uuid::new_v4().to_string(),
-                        addr: addr_copy,
-                        config: conf
+                        id,
+                        player: snake_index,
+                        name,
                     }
                 })
             })
@@ -91,36 +74,68 @@ pub async fn run_game(timeout_ms: u64, snakes_addrs: &[String], width: UnitAbs,
 
     if let Err(e) = live_snakes {
         error!("Some snake(s) failed to respond to the start call: {}", &e);
-        return;
+        return None;
     }
     let mut live_snakes: Vec<LiveSnake> = live_snakes.unwrap();
+    let mut eliminated: Vec<(LiveSnake, Elimination, Option<LiveSnake>)> = Vec::new();
 
-    while board.snakes.len() > 1 {
+    while !board.game_over() {
+        let turn = board.turn();
         info!("Turn {}: {} snakes\n{}", turn, board.snakes.len(), draw_board(&board));
         if prompt {
             wait_for_prompt();
         }
 
         info!("Requesting moves for turn {}. Snakes have {} ms to respond", turn, timeout_ms);
-        let snake_moves = future::join_all(
+        let identities = live_snakes.iter().map(|snake| (snake.id.as_str(), snake.name.as_str())).collect::<Vec<_>>();
+        let (snake_moves, latencies): (Vec<ApiDirection>, Vec<u64>) = future::join_all(
             board.snakes.iter().enumerate().map(|(snake_index, snake)| {
                 let default_move = snake.get_default_move();
-                let game_state = build_api_game_state(&board, snake_index, turn, &game_id);
-                let addr_copy = live_snakes.get(snake_index).unwrap().addr.clone();
-                get_move(&client, addr_copy, game_state, timeout_ms).map(move |call_result| {
-                    call_result.map(|api_move| api_move.decision).unwrap_or_else(|err| {
+                let game_state = build_api_game_state(&board, snake_index, &game_id, timeout_ms, &identities);
+                let player = &players[live_snakes[snake_index].player];
+                let start = Instant::now();
+                player.get_move(game_state, timeout_ms, seed).map(move |call_result| {
+                    let decision = call_result.map(|api_move| api_move.decision).unwrap_or_else(|err| {
                         warn!("Using default move for snakes: {}", &err);
                         default_move
-                    })
+                    });
+                    (decision, start.elapsed().as_millis() as u64)
                 })
             })
-        ).await;
+        ).await.into_iter().unzip();
 
-        let dead_snake_indices = board.advance(true, &snake_moves);
+        let mut frame = config.replay.as_ref().map(|_| ReplayFrame {
+            state: build_api_game_state(&board, 0, &game_id, timeout_ms, &identities),
+            moves: live_snakes.iter().zip(snake_moves.iter().zip(latencies.iter()))
+                .map(|(snake, (&decision, &latency))| ReplayMove {id: snake.id.clone(), decision, latency})
+                .collect(),
+            eliminations: Vec::new(),
+        });
+        let dead_snake_indices = play_turn(&mut board, &snake_moves, &mut rng);
+        if let (Some(path), Some(frame)) = (config.replay.as_ref(), frame.as_mut()) {
+            let ids = live_snakes.iter().map(|snake| snake.id.clone()).collect::<Vec<_>>();
+            frame.eliminations = dead_snake_indices.iter()
+                .map(|(&snake_index, elimination)| ReplayElimination::new(&ids[snake_index], elimination, &ids))
+                .collect();
+            if let Err(e) = replay::append(path, frame) {
+                error!("Couldn't record turn to replay {}: {}", path, e);
+            }
+        }
 
         //todo: notify dead snakes about /end
         if !dead_snake_indices.is_empty() {
-            info!("Snakes died: {:?}", &dead_snake_indices);
+            for (&snake_index, elimination) in dead_snake_indices.iter() {
+                let snake = live_snakes[snake_index].clone();
+                let killer = elimination.by.filter(|&by| by != snake_index).map(|by| live_snakes[by].clone());
+                info!(
+                    "Snake @ {} eliminated on turn {}: {:?}{}",
+                    snake.name,
+                    elimination.turn,
+                    elimination.cause,
+                    killer.as_ref().map(|killer| format!(" by snake @ {}", killer.name)).unwrap_or_default()
+                );
+                eliminated.push((snake, *elimination, killer));
+            }
             live_snakes = live_snakes.iter().enumerate()
                 .filter_map(|(i, ls)| {
                     if dead_snake_indices.contains_key(&i) {
@@ -131,15 +146,60 @@ pub async fn run_game(timeout_ms: u64, snakes_addrs: &[String], width: UnitAbs,
                 })
                 .collect();
         }
+    }
 
-        turn += 1;
+    //the final board, with nothing left to happen
+    if let Some(path) = config.replay.as_ref() {
+        let identities = live_snakes.iter().map(|snake| (snake.id.as_str(), snake.name.as_str())).collect::<Vec<_>>();
+        let frame = ReplayFrame {
+            state: build_api_game_state(&board, 0, &game_id, timeout_ms, &identities),
+            moves: Vec::new(),
+            eliminations: Vec::new(),
+        };
+        if let Err(e) = replay::append(path, &frame) {
+            error!("Couldn't record turn to replay {}: {}", path, e);
+        }
+        info!("Recorded replay to {}", path);
     }
 
-    info!("Game has ended!");
     //notify winner (may be none if both died in final turn)
+    info!("Game has ended after {} turns", board.turn());
+    eliminated.sort_by_key(|(_, elimination, _)| elimination.turn);
+    for (snake, elimination, killer) in eliminated.iter() {
+        info!(
+            "  Turn {}: snake @ {} {:?}{}",
+            elimination.turn,
+            snake.name,
+            elimination.cause,
+            killer.as_ref().map(|killer| format!(" by snake @ {}", killer.name)).unwrap_or_default()
+        );
+    }
+    for snake in live_snakes.iter() {
+        info!("  Survived: snake @ {}", snake.name);
+    }
+    Some(live_snakes.into_iter().map(|snake| snake.name).collect())
 }
 
+//how each player's snake is named in the game. players sharing a name, like two builtins with the same strategy, are
+//numbered so their snakes can be told apart
+fn player_names(players: &[Box<dyn Player>]) -> Vec<String> {
+    players.iter().enumerate().map(|(i, player)| {
+        let name = player.name();
+        if players.iter().filter(|other| other.name() == name).count() > 1 {
+            format!("{}#{}", name, players[..i].iter().filter(|other| other.name() == name).count() + 1)
+        } else {
+            String::from(name)
+        }
+    }).collect()
+}
 
+//everything that happens between collecting moves and asking for the next ones
+pub fn play_turn(board: &mut Board, snake_moves: &[ApiDirection], rng: &mut dyn RngCore) -> Eliminations {
+    let eliminations = board.advance(Some(&mut *rng), snake_moves);
+    let turn = board.turn();
+    board.mode().ruleset().update_hazards(board, turn, rng);
+    eliminations
+}
 
 fn wait_for_prompt() {
     info!("Press [ENTER] to continue");
@@ -147,22 +207,46 @@ fn wait_for_prompt() {
     io::stdin().read_line(&mut input).unwrap();
 }
 
-fn build_api_game_state(board: &Board, snake_index: usize, turn: u32, game_id: &str) -> ApiGameState {
+//`identities` are the id and name of each snake on the board, which shouldn't change as others are eliminated
+pub fn build_api_game_state(board: &Board, snake_index: usize, game_id: &str, timeout_ms: u64, identities: &[(&str, &str)]) -> ApiGameState {
     ApiGameState {
-        game: ApiGame {id: String::from(game_id)},
-        turn,
+        game: ApiGame {
+            id: String::from(game_id),
+            ruleset: Some(ApiRuleset {
+                name: String::from(board.mode().name()),
+                settings: ApiRulesetSettings {
+                    food_spawn_chance: board.settings().food_spawn_chance,
+                    minimum_food: board.settings().minimum_food,
+                    hazard_damage_per_turn: board.settings().hazard_damage as u32,
+                    royale: ApiRoyaleSettings {
+                        shrink_every_n_turns: board.settings().shrink_every_n_turns,
+                    },
+                    squad: ApiSquadSettings {
+                        allow_body_collisions: board.settings().allow_body_collisions,
+                        shared_elimination: board.settings().shared_elimination,
+                        shared_health: board.settings().shared_health,
+                        shared_length: board.settings().shared_length,
+                    },
+                },
+                ..Default::default()
+            }),
+            timeout: Some(timeout_ms as u32),
+        },
+        turn: board.turn(),
         board: ApiBoard {
             height: board.height() as u32,
             width: board.width() as u32,
             food: board.food.iter().map(ApiCoords::from).collect(),
             snakes: board.snakes.iter()
-                .enumerate()
-                .map(|(i, snake)| {
-                    build_api_snake(snake, &format!("id_{}", i), &format!("name_{}", i))
-                })
-                .collect()
+                .zip(identities.iter())
+                .map(|(snake, (id, name))| build_api_snake(snake, id, name))
+                .collect(),
+            hazards: board.hazards.iter().map(ApiCoords::from).collect(),
         },
-        you: build_api_snake(board.snakes.get(snake_index).unwrap(), &format!("id_{}", snake_index), &format!("name_{}", snake_index)),
+        //nobody is left to be `you` once the game ends in a draw
+        you: board.snakes.get(snake_index)
+            .map(|snake| build_api_snake(snake, identities[snake_index].0, identities[snake_index].1))
+            .unwrap_or_default(),
     }
 }
 
@@ -171,6 +255,72 @@ fn build_api_snake(snake: &Snake, id: &str, name: &str) -> ApiSnake {
         id: String::from(id),
         name: String::from(name),
         health: snake.health as u32,
-        body: snake.body.nodes.iter().map(ApiCoords::from).collect()
+        body: snake.body.nodes.iter().map(ApiCoords::from).collect(),
+        head: Some(ApiCoords::from(&snake.head())),
+        length: Some(snake.size() as u32),
+        squad: snake.squad.map(|squad| squad.to_string()),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use futures::future::BoxFuture;
+    use crate::brain::{SearchOptions, Strategy};
+    use crate::player::BuiltinPlayer;
+
+    //passes everything through, counting the moves asked for
+    struct Counted(BuiltinPlayer, Arc<AtomicUsize>);
+
+    impl Player for Counted {
+        fn name(&self) -> &str {
+            self.0.name()
+        }
+
+        fn start(&self, game_state: ApiGameState) -> BoxFuture<'_, Result<ApiSnakeConfig, String>> {
+            self.0.start(game_state)
+        }
+
+        fn get_move(&self, game_state: ApiGameState, timeout_ms: u64, seed: u64) -> BoxFuture<'_, Result<ApiMove, String>> {
+            self.1.fetch_add(1, Ordering::Relaxed);
+            self.0.get_move(game_state, timeout_ms, seed)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_builtin_game() {
+        let counts = [Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0))];
+        let players: Vec<Box<dyn Player>> = vec![
+            Box::new(Counted(BuiltinPlayer::new(Strategy::AlphaBeta, SearchOptions::default()), counts[0].clone())),
+            Box::new(Counted(BuiltinPlayer::new(Strategy::Mcts, SearchOptions::default()), counts[1].clone())),
+        ];
+        let config = GameConfig {
+            timeout_ms: 20,
+            width: 5,
+            height: 5,
+            mode: GameMode::Standard,
+            settings: Settings::default(),
+            seed: 1,
+            prompt: false,
+            replay: None,
+        };
+        let survivors = run_game(&config, &players).await.unwrap();
+        assert!(survivors.len() <= 1);
+        //neither snake can die before its first move, so both were asked at least once
+        assert!(counts[0].load(Ordering::Relaxed) >= 1);
+        assert!(counts[1].load(Ordering::Relaxed) >= 1);
+    }
+
+    #[test]
+    fn test_player_names() {
+        let players: Vec<Box<dyn Player>> = vec![
+            Box::new(BuiltinPlayer::new(Strategy::AlphaBeta, SearchOptions::default())),
+            Box::new(BuiltinPlayer::new(Strategy::Mcts, SearchOptions::default())),
+            Box::new(BuiltinPlayer::new(Strategy::AlphaBeta, SearchOptions::default())),
+        ];
+        assert_eq!(player_names(&players), vec!["builtin:alphabeta#1", "builtin:mcts", "builtin:alphabeta#2"]);
     }
 }
@@ -5,8 +5,21 @@ mod host;
 mod benchmark;
 mod util;
 mod brain;
+mod heuristic;
+mod alphabeta;
+mod mcts;
+mod session;
+mod ponder;
+mod player;
+mod tune;
+mod replay;
+use std::net::SocketAddr;
 use clap::{App, Arg, SubCommand};
 use util::init_logger;
+use api::ApiGameState;
+use game::{Board, Settings};
+use brain::{Score, SearchOptions};
+use heuristic::Weights;
 
 #[tokio::main]
 async fn main() {
@@ -29,10 +42,52 @@ async fn main() {
             )
             .arg(Arg::with_name("budget")
                 .short("b")
-                .help("Time budget for responding to /move requests, in milliseconds")
+                .help("Time budget for responding to /move requests, in milliseconds. Only used if the engine doesn't send a game timeout")
                 .takes_value(true)
                 .default_value("200")
             )
+            .arg(Arg::with_name("margin")
+                .short("m")
+                .help("Safety margin subtracted from the game timeout along with observed network latency, in milliseconds")
+                .takes_value(true)
+                .default_value("50")
+            )
+            .arg(Arg::with_name("ponder")
+                .short("P")
+                .help("Keep searching likely next boards while waiting for the next /move request")
+                .takes_value(false)
+                .required(false)
+            )
+            .arg(Arg::with_name("strategy")
+                .short("s")
+                .help("How to search for moves: bestfirst, alphabeta, or mcts. Pondering only applies to bestfirst")
+                .takes_value(true)
+                .default_value("bestfirst")
+            )
+            .arg(Arg::with_name("adversary")
+                .short("a")
+                .help("How bestfirst expects enemies to move: paranoid (all against us), maxn (each for itself), or bestreply (each for itself, except the most dangerous)")
+                .takes_value(true)
+                .default_value("paranoid")
+            )
+            .arg(Arg::with_name("risk")
+                .short("A")
+                .help("Risk aversion: how much bestfirst weighs the worst case against the expected outcome given likely enemy moves, from 0 to 1")
+                .takes_value(true)
+                .default_value("1")
+            )
+            .arg(Arg::with_name("replay")
+                .short("R")
+                .help("Directory to record each game's /move requests to, as a replay file per game")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("weights")
+                .short("w")
+                .help("JSON file of heuristic component weights, like {\"territory\": 1, \"length\": 0.5}. Unlisted components keep their defaults")
+                .takes_value(true)
+                .required(false)
+            )
         )
         .subcommand(SubCommand::with_name("host")
             .about("Host a match between snakes.")
@@ -60,14 +115,137 @@ async fn main() {
                 .takes_value(true)
                 .default_value("11")
             )
+            .arg(Arg::with_name("ruleset")
+                .short("r")
+                .help("Game mode rules to play by: standard, solo, royale, constrictor, wrapped, or squad")
+                .takes_value(true)
+                .default_value("standard")
+            )
+            .arg(Arg::with_name("food_chance")
+                .short("f")
+                .help("Percent chance of spawning food each turn once the minimum is met")
+                .takes_value(true)
+                .default_value("15")
+            )
+            .arg(Arg::with_name("minimum_food")
+                .short("m")
+                .help("Food is spawned each turn to keep at least this many on the board")
+                .takes_value(true)
+                .default_value("1")
+            )
+            .arg(Arg::with_name("hazard_damage")
+                .short("d")
+                .help("Health lost each turn a snake's head is in a hazard")
+                .takes_value(true)
+                .default_value("14")
+            )
+            .arg(Arg::with_name("shrink")
+                .short("n")
+                .help("Royale mode shrinks the safe zone every N turns")
+                .takes_value(true)
+                .default_value("25")
+            )
+            .arg(Arg::with_name("seed")
+                .short("S")
+                .long("seed")
+                .help("Seed for starting positions, food and hazards, to replay a game exactly. Random if not given")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("replay")
+                .short("R")
+                .help("File to record the game to, for viewing with the replay subcommand")
+                .takes_value(true)
+                .required(false)
+            )
             .arg(Arg::with_name("snake")
                 .short("s")
-                .help("API endpoint URL(s) of participant snakes")
+                .help("Participant snakes: API endpoint URLs, or builtin:<strategy>[:<weights file>] to run our own search in-process")
                 .takes_value(true)
                 .multiple(true)
                 .default_value("localhost:8080")
             )
         )
+        .subcommand(SubCommand::with_name("tune")
+            .about("Tune heuristic weights with SPSA, playing in-process games between slightly different variants.")
+            .arg(Arg::with_name("iterations")
+                .short("n")
+                .help("Number of SPSA iterations")
+                .takes_value(true)
+                .default_value("50")
+            )
+            .arg(Arg::with_name("games")
+                .short("g")
+                .help("Games played per iteration, in pairs with sides swapped")
+                .takes_value(true)
+                .default_value("4")
+            )
+            .arg(Arg::with_name("depth")
+                .short("d")
+                .help("Turns both variants look ahead with alpha-beta search. Fixed instead of timed, so a seed always replays the same games")
+                .takes_value(true)
+                .default_value("3")
+            )
+            .arg(Arg::with_name("width")
+                .short("w")
+                .help("Width of the game board")
+                .takes_value(true)
+                .default_value("11")
+            )
+            .arg(Arg::with_name("height")
+                .short("h")
+                .help("Height of the game board")
+                .takes_value(true)
+                .default_value("11")
+            )
+            .arg(Arg::with_name("ruleset")
+                .short("r")
+                .help("Game mode rules to play by: standard, royale, constrictor, or wrapped")
+                .takes_value(true)
+                .default_value("standard")
+            )
+            .arg(Arg::with_name("seed")
+                .short("S")
+                .long("seed")
+                .help("Seed for starting positions, food, hazards and weight perturbations, so a tuning run can be repeated")
+                .takes_value(true)
+                .default_value("0")
+            )
+            .arg(Arg::with_name("from")
+                .short("f")
+                .help("JSON file of heuristic weights to start from, otherwise the defaults")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("out")
+                .short("o")
+                .help("JSON file the tuned weights are written to after every iteration, for loading with server -w")
+                .takes_value(true)
+                .default_value("weights.json")
+            )
+        )
+        .subcommand(SubCommand::with_name("replay")
+            .about("Step forwards and backwards through a recorded game.")
+            .arg(Arg::with_name("file")
+                .help("Replay file recorded by host or server mode")
+                .required(true)
+                .index(1)
+            )
+        )
+        .subcommand(SubCommand::with_name("explain")
+            .about("Print each heuristic component's contribution to every snake's score for a game state.")
+            .arg(Arg::with_name("state")
+                .help("JSON file of a game state, as sent to /move")
+                .required(true)
+                .index(1)
+            )
+            .arg(Arg::with_name("weights")
+                .short("w")
+                .help("JSON file of heuristic component weights")
+                .takes_value(true)
+                .required(false)
+            )
+        )
         .subcommand(SubCommand::with_name("benchmark")
             .about("Execute a series of performance tests, logging results.")
         )
@@ -76,19 +254,72 @@ async fn main() {
     match matches.subcommand() {
         ("server", Some(args)) => {
             server::start_server(
-                args.value_of("ip").unwrap().parse().expect("IP must be an IPV6 or IPV4 format"),
-                args.value_of("port").unwrap().parse().expect("Port must be numeric"),
-                args.value_of("budget").unwrap().parse().expect("Time budget must be numeric")
+                SocketAddr::new(
+                    args.value_of("ip").unwrap().parse().expect("IP must be an IPV6 or IPV4 format"),
+                    args.value_of("port").unwrap().parse().expect("Port must be numeric")
+                ),
+                args.value_of("budget").unwrap().parse().expect("Time budget must be numeric"),
+                args.value_of("margin").unwrap().parse().expect("Margin must be numeric"),
+                args.is_present("ponder"),
+                args.value_of("strategy").unwrap().parse().unwrap_or_else(|e: String| panic!("{}", e)),
+                SearchOptions {
+                    adversary: args.value_of("adversary").unwrap().parse().unwrap_or_else(|e: String| panic!("{}", e)),
+                    risk_aversion: parse_risk_aversion(args.value_of("risk").unwrap()),
+                    weights: load_weights(args.value_of("weights")),
+                },
+                args.value_of("replay").map(String::from)
             ).await;
         }
         ("host", Some(args)) => {
-            host::run_game(
-                args.value_of("timeout").unwrap().parse().expect("Timeout must be numeric"),
-                &args.values_of("snake").expect("At least one snake is needed").map(String::from).collect::<Vec<_>>(),
-                args.value_of("width").unwrap().parse().expect("Width must be numeric"),
-                args.value_of("height").unwrap().parse().expect("Height must be numeric"),
-                args.is_present("prompt")
-            ).await;
+            let config = host::GameConfig {
+                timeout_ms: args.value_of("timeout").unwrap().parse().expect("Timeout must be numeric"),
+                width: args.value_of("width").unwrap().parse().expect("Width must be numeric"),
+                height: args.value_of("height").unwrap().parse().expect("Height must be numeric"),
+                mode: args.value_of("ruleset").unwrap().parse().unwrap_or_else(|e: String| panic!("{}", e)),
+                settings: Settings {
+                    food_spawn_chance: args.value_of("food_chance").unwrap().parse().expect("Food chance must be numeric"),
+                    minimum_food: args.value_of("minimum_food").unwrap().parse().expect("Minimum food must be numeric"),
+                    hazard_damage: args.value_of("hazard_damage").unwrap().parse().expect("Hazard damage must be numeric"),
+                    shrink_every_n_turns: args.value_of("shrink").unwrap().parse().expect("Shrink turns must be numeric"),
+                    ..Settings::default()
+                },
+                seed: args.value_of("seed").map(|seed| seed.parse().expect("Seed must be numeric")).unwrap_or_else(rand::random),
+                prompt: args.is_present("prompt"),
+                replay: args.value_of("replay").map(String::from),
+            };
+            let players = args.values_of("snake").expect("At least one snake is needed")
+                .map(|spec| player::parse_player(spec).unwrap_or_else(|e| panic!("{}", e)))
+                .collect::<Vec<_>>();
+            host::run_game(&config, &players).await;
+        }
+        ("tune", Some(args)) => {
+            let config = tune::TuneConfig {
+                iterations: args.value_of("iterations").unwrap().parse().expect("Iterations must be numeric"),
+                games: args.value_of("games").unwrap().parse().expect("Games must be numeric"),
+                depth: args.value_of("depth").unwrap().parse().expect("Depth must be numeric"),
+                width: args.value_of("width").unwrap().parse().expect("Width must be numeric"),
+                height: args.value_of("height").unwrap().parse().expect("Height must be numeric"),
+                mode: args.value_of("ruleset").unwrap().parse().unwrap_or_else(|e: String| panic!("{}", e)),
+                settings: Settings::default(),
+                seed: args.value_of("seed").unwrap().parse().expect("Seed must be numeric"),
+                out: String::from(args.value_of("out").unwrap()),
+            };
+            tune::tune(&config, load_weights(args.value_of("from")));
+        }
+        ("replay", Some(args)) => {
+            if let Err(e) = replay::run_replay(args.value_of("file").unwrap()) {
+                eprintln!("{}", e);
+            }
+        }
+        ("explain", Some(args)) => {
+            let path = args.value_of("state").unwrap();
+            let json = std::fs::read_to_string(path).unwrap_or_else(|e| panic!("Couldn't read {}: {}", path, e));
+            let game_state: ApiGameState = serde_json::from_str(&json).expect("Game state must be valid JSON");
+            let board = Board::from_api(&game_state);
+            let weights = load_weights(args.value_of("weights"));
+            for (snake_index, name) in Board::api_snakes(&game_state).map(|snake| &snake.name).enumerate() {
+                println!("{}\n{}", name, weights.explain(&board, snake_index));
+            }
         }
         ("benchmark", _) => {
             benchmark::run_benchmark();
@@ -98,3 +329,13 @@ async fn main() {
         }
     }
 }
+
+fn load_weights(path: Option<&str>) -> Weights {
+    path.map(|path| Weights::load(path).unwrap_or_else(|e| panic!("{}", e))).unwrap_or_default()
+}
+
+fn parse_risk_aversion(value: &str) -> Score {
+    let risk_aversion: Score = value.parse().expect("Risk aversion must be numeric");
+    assert!((0.0..=1.0).contains(&risk_aversion), "Risk aversion must be from 0 to 1");
+    risk_aversion
+}
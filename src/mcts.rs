@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, Duration};
+use log::*;
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use rayon::prelude::*;
+use crate::api::{ApiDirection, ApiGameState, ALL_DIRS};
+use crate::brain::Score;
+use crate::game::{Board, UndoToken};
+use crate::heuristic::Weights;
+
+//exploration constant for UCB1, with rewards between 0 and 1
+const EXPLORATION: Score = 1.4;
+//random turns played out from a new node before scoring the board
+const ROLLOUT_TURNS: usize = 10;
+//reward for still being alive at the end of a rollout, before the heuristic's share
+const SURVIVAL_REWARD: Score = 0.5;
+//only check the clock once per this many iterations
+const CLOCK_INTERVAL: usize = 16;
+
+#[derive(Copy, Clone, Default)]
+struct MoveStats {
+    visits: u32,
+    reward: Score,
+}
+
+//simultaneous moves are handled by decoupling: each snake picks its own move from its own stats, as if the others' were
+//part of the environment. the joint move then picks the child
+struct MctsNode {
+    //original index of each snake still on the board here
+    ids: Vec<usize>,
+    //moves each snake can make here, and how each has worked out for that snake
+    moves: Vec<Vec<ApiDirection>>,
+    stats: Vec<Vec<MoveStats>>,
+    visits: u32,
+    //by joint move, numbering each snake's choice in mixed radix
+    children: HashMap<usize, usize>,
+    terminal: bool,
+}
+
+impl MctsNode {
+    //the root is never terminal, since we have to move even if the game already looks over
+    fn new(board: &Board, ids: Vec<usize>, is_root: bool) -> MctsNode {
+        let terminal = !is_root && (ids.first() != Some(&0) || board.game_over());
+        let moves = if terminal {Vec::new()} else {board.enumerate_snake_moves(&board.bits())};
+        MctsNode {
+            stats: moves.iter().map(|dirs| vec![MoveStats::default(); dirs.len()]).collect(),
+            ids,
+            moves,
+            visits: 0,
+            children: HashMap::new(),
+            terminal,
+        }
+    }
+
+    //decoupled UCB1: each snake tries every move once, then balances its mean reward against how little it's been tried
+    fn select(&self) -> Vec<usize> {
+        let ln_visits = (self.visits.max(1) as Score).ln();
+        self.stats.iter().map(|stats| {
+            if let Some(untried) = stats.iter().position(|stat| stat.visits == 0) {
+                return untried;
+            }
+            let ucb = |stat: &MoveStats| stat.reward / stat.visits as Score + EXPLORATION * (ln_visits / stat.visits as Score).sqrt();
+            (0..stats.len())
+                .max_by(|&a, &b| ucb(&stats[a]).partial_cmp(&ucb(&stats[b])).unwrap())
+                .unwrap()
+        }).collect()
+    }
+
+    fn joint_index(&self, choices: &[usize]) -> usize {
+        choices.iter().zip(self.moves.iter()).fold(0, |index, (&choice, dirs)| index * dirs.len() + choice)
+    }
+}
+
+//a single-threaded tree; workers each grow their own and merge the root's stats at the end
+struct Tree {
+    root: Board,
+    n_snakes: usize,
+    nodes: Vec<MctsNode>,
+    n_iterations: usize,
+    max_depth: usize,
+    weights: Weights,
+}
+
+impl Tree {
+    fn new(root: &Board, weights: Weights) -> Tree {
+        Tree {
+            nodes: vec![MctsNode::new(root, (0..root.snakes.len()).collect(), true)],
+            n_snakes: root.snakes.len(),
+            root: root.clone(),
+            n_iterations: 0,
+            max_depth: 0,
+            weights,
+        }
+    }
+
+    fn run(&mut self, deadline: SystemTime, rng: &mut StdRng) {
+        let mut board = self.root.clone();
+        loop {
+            if self.n_iterations.is_multiple_of(CLOCK_INTERVAL) && SystemTime::now() >= deadline {
+                break;
+            }
+            self.iterate(&mut board, rng);
+        }
+    }
+
+    //select down the tree, add one node, play it out, then credit every snake's choices along the way
+    fn iterate(&mut self, board: &mut Board, rng: &mut StdRng) {
+        let mut path: Vec<(usize, Vec<usize>)> = Vec::new();
+        let mut undos: Vec<UndoToken> = Vec::new();
+        let mut node_index = 0;
+        let rewards = loop {
+            let node = &self.nodes[node_index];
+            if node.terminal {
+                break self.score(board, &node.ids);
+            }
+            let choices = node.select();
+            let joint_index = node.joint_index(&choices);
+            let snake_moves = choices.iter().zip(node.moves.iter()).map(|(&choice, dirs)| dirs[choice]).collect::<Vec<_>>();
+            let undo = board.apply(None, &snake_moves);
+            let ids = node.ids.iter().enumerate()
+                .filter(|(snake_index, _)| !undo.eliminations.contains_key(snake_index))
+                .map(|(_, &id)| id)
+                .collect::<Vec<_>>();
+            let child = node.children.get(&joint_index).copied();
+            undos.push(undo);
+            path.push((node_index, choices));
+
+            match child {
+                Some(child) => node_index = child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes.push(MctsNode::new(board, ids.clone(), false));
+                    self.nodes[node_index].children.insert(joint_index, child);
+                    break self.rollout(board, ids, &self.nodes[child].moves, rng);
+                },
+            }
+        };
+
+        self.max_depth = self.max_depth.max(path.len());
+        for (node_index, choices) in path {
+            let node = &mut self.nodes[node_index];
+            node.visits += 1;
+            for (snake_index, &choice) in choices.iter().enumerate() {
+                let stat = &mut node.stats[snake_index][choice];
+                stat.visits += 1;
+                stat.reward += rewards[node.ids[snake_index]];
+            }
+        }
+        while let Some(undo) = undos.pop() {
+            board.undo(undo);
+        }
+        self.n_iterations += 1;
+    }
+
+    //random but not suicidal moves, with food spawning, until we die or run out of turns.
+    //`first_moves` are the ones the new node already found for `board`
+    fn rollout(&self, board: &Board, mut ids: Vec<usize>, first_moves: &[Vec<ApiDirection>], rng: &mut StdRng) -> Vec<Score> {
+        let mut board = board.clone();
+        let mut enumerated;
+        for turn in 0..ROLLOUT_TURNS {
+            if ids.first() != Some(&0) || board.game_over() {
+                break;
+            }
+            let free_moves = if turn == 0 {
+                first_moves
+            } else {
+                enumerated = board.enumerate_snake_moves(&board.bits());
+                &enumerated
+            };
+            let snake_moves = free_moves.iter()
+                .map(|dirs| *dirs.choose(rng).unwrap())
+                .collect::<Vec<_>>();
+            let eliminations = board.advance(Some(&mut *rng), &snake_moves);
+            ids = ids.into_iter().enumerate()
+                .filter(|(snake_index, _)| !eliminations.contains_key(snake_index))
+                .map(|(_, id)| id)
+                .collect();
+        }
+        self.score(&board, &ids)
+    }
+
+    //by original snake index: nothing for eliminated snakes, everything for a winner. otherwise surviving is worth half,
+    //since heuristic scores can be small enough to get lost in the exploration term
+    fn score(&self, board: &Board, ids: &[usize]) -> Vec<Score> {
+        let mut rewards = vec![0.0; self.n_snakes];
+        if ids.len() == 1 && board.game_over() {
+            rewards[ids[0]] = 1.0;
+        } else {
+            for (&id, h_score) in ids.iter().zip(self.weights.scores(board)) {
+                rewards[id] = SURVIVAL_REWARD + (1.0 - SURVIVAL_REWARD) * h_score;
+            }
+        }
+        rewards
+    }
+
+    //our visits and total reward for each direction from the root, indexed by ApiDirection
+    fn root_stats(&self) -> [MoveStats; 4] {
+        let mut root_stats = [MoveStats::default(); 4];
+        let root = &self.nodes[0];
+        if let (Some(dirs), Some(stats)) = (root.moves.first(), root.stats.first()) {
+            for (dir, stat) in dirs.iter().zip(stats.iter()) {
+                root_stats[dir.as_index()] = *stat;
+            }
+        }
+        root_stats
+    }
+}
+
+//grows a tree on every core until the budget elapses, then picks our most visited move.
+//each worker's rollouts are random, but drawn from `seed` mixed with the turn and the worker's index
+pub fn get_decision(game_state: &ApiGameState, budget: Duration, weights: Weights, seed: u64) -> ApiDirection {
+    let board = Board::from_api(game_state);
+    let deadline = SystemTime::now() + budget;
+    let trees = (0..rayon::current_num_threads()).into_par_iter()
+        .map(|worker| {
+            let mut rng = StdRng::seed_from_u64(seed ^ ((game_state.turn as u64) << 32) ^ worker as u64);
+            let mut tree = Tree::new(&board, weights);
+            tree.run(deadline, &mut rng);
+            tree
+        })
+        .collect::<Vec<_>>();
+
+    let mut root_stats = [MoveStats::default(); 4];
+    for tree in trees.iter() {
+        for (total, stat) in root_stats.iter_mut().zip(tree.root_stats().iter()) {
+            total.visits += stat.visits;
+            total.reward += stat.reward;
+        }
+    }
+    let best = (0..ALL_DIRS.len())
+        .filter(|&i| root_stats[i].visits > 0)
+        .max_by_key(|&i| root_stats[i].visits);
+    info!(
+        "Budget elapsed: n_considered={}, depth={}, score={}",
+        trees.iter().map(|tree| tree.n_iterations).sum::<usize>(),
+        trees.iter().map(|tree| tree.max_depth).max().unwrap_or(0),
+        best.map(|i| root_stats[i].reward / root_stats[i].visits as Score).unwrap_or(0.0)
+    );
+    best.map(|i| ALL_DIRS[i]).unwrap_or_else(|| board.you().get_default_move())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::ApiDirection::*;
+
+    macro_rules! decide {
+        ($s:expr) => {
+            get_decision(&ApiGameState::parse_basic($s), Duration::from_millis(200), Weights::default(), 0)
+        };
+    }
+
+    #[test]
+    fn test_iterate_restores_board() {
+        let board = Board::from_api(&ApiGameState::parse_basic("
+        |  |  |  |  |  |
+        |  |Y0|  |()|  |
+        |  |Y1|  |A1|A0|
+        |  |  |  |  |  |
+        "));
+        let mut tree = Tree::new(&board, Weights::default());
+        let mut working = board.clone();
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..200 {
+            tree.iterate(&mut working, &mut rng);
+            assert_eq!(working, board);
+        }
+        assert_eq!(tree.nodes[0].visits, 200);
+        let visits: u32 = tree.root_stats().iter().map(|stat| stat.visits).sum();
+        assert_eq!(visits, 200);
+
+        //the same seed grows the same tree
+        let grow = |seed| {
+            let mut tree = Tree::new(&board, Weights::default());
+            let mut working = board.clone();
+            let mut rng = StdRng::seed_from_u64(seed);
+            for _ in 0..200 {
+                tree.iterate(&mut working, &mut rng);
+            }
+            tree.root_stats().iter().map(|stat| (stat.visits, stat.reward)).collect::<Vec<_>>()
+        };
+        assert_eq!(grow(1), grow(1));
+    }
+
+    #[test]
+    fn test_select_untried_first() {
+        let board = Board::from_api(&ApiGameState::parse_basic("
+        |  |  |  |
+        |  |Y0|  |
+        |  |Y1|  |
+        "));
+        let mut node = MctsNode::new(&board, vec![0], true);
+        node.stats[0][0] = MoveStats {visits: 5, reward: 5.0};
+        node.visits = 5;
+        assert_eq!(node.select(), vec![1]);
+    }
+
+    #[test]
+    fn test_avoid_walls() {
+        assert_eq!(Down, decide!("
+        |Y0|Y1|Y2|
+        |  |  |  |
+        |  |  |  |
+        "));
+    }
+
+    #[test]
+    fn test_enemy_already_trapped() {
+        assert_eq!(Right, decide!("
+        |  |Y0|  |  |  |
+        |  |Y1|  |  |  |
+        |A0|Y2|  |  |  |
+        |A1|Y3|Y4|  |  |
+        |A2|A3|A4|  |  |
+        "));
+    }
+}
@@ -0,0 +1,170 @@
+use std::sync::OnceLock;
+use std::time::Duration;
+use futures::future::{BoxFuture, FutureExt};
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+use hyper::{Client, Request, Body, body, client::connect::HttpConnector};
+use crate::api::{ApiDirection, ApiGameState, ApiMove, ApiSnakeConfig};
+use crate::brain::{self, SearchOptions, Strategy};
+use crate::{alphabeta, mcts};
+use crate::heuristic::Weights;
+use crate::server::{COLOR, HEAD, TAIL};
+
+const START_TIMEOUT_MS: u64 = 5000;
+//taken off a builtin player's move timeout to cover the host's own overhead
+const BUILTIN_MARGIN_MS: u64 = 10;
+
+//a snake the host can run a game with
+pub trait Player: Send + Sync {
+    //how the host refers to this player in logs
+    fn name(&self) -> &str;
+    fn start(&self, game_state: ApiGameState) -> BoxFuture<'_, Result<ApiSnakeConfig, String>>;
+    //`seed` is the game's, for players whose searches are random
+    fn get_move(&self, game_state: ApiGameState, timeout_ms: u64, seed: u64) -> BoxFuture<'_, Result<ApiMove, String>>;
+}
+
+//a snake server reached over the API
+pub struct HttpPlayer {
+    client: Client<HttpConnector>,
+    addr: String,
+}
+
+impl HttpPlayer {
+    pub fn new(addr: &str) -> HttpPlayer {
+        HttpPlayer {
+            client: Client::default(),
+            addr: String::from(addr),
+        }
+    }
+
+    async fn post<T: serde::de::DeserializeOwned>(&self, path: &str, game_state: &ApiGameState, timeout_ms: u64) -> Result<T, String> {
+        let req = Request::post(format!("{}{}", self.addr, path))
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_string(game_state).unwrap()))
+            .unwrap();
+        match timeout(Duration::from_millis(timeout_ms), self.client.request(req)).await {
+            Err(_) => {
+                Err(format!("Snake @ {} timed out after {} ms", self.addr, timeout_ms))
+            },
+            Ok(Err(e)) => {
+                Err(format!("Snake @ {} failed to reply: {}", self.addr, e))
+            },
+            Ok(Ok(res)) => {
+                let res_body = body::to_bytes(res.into_body());
+                match serde_json::from_slice::<T>(&res_body.await.unwrap()) {
+                    Ok(response) => Ok(response),
+                    Err(e) => Err(format!("Snake @ {} responded with invalid JSON: {}", self.addr, e))
+                }
+            }
+        }
+    }
+}
+
+impl Player for HttpPlayer {
+    fn name(&self) -> &str {
+        &self.addr
+    }
+
+    fn start(&self, game_state: ApiGameState) -> BoxFuture<'_, Result<ApiSnakeConfig, String>> {
+        async move {
+            self.post("/start", &game_state, START_TIMEOUT_MS).await
+        }.boxed()
+    }
+
+    fn get_move(&self, game_state: ApiGameState, timeout_ms: u64, _seed: u64) -> BoxFuture<'_, Result<ApiMove, String>> {
+        async move {
+            self.post("/move", &game_state, timeout_ms).await
+        }.boxed()
+    }
+}
+
+//runs a search in-process, the same way the server would for this strategy, minus pondering and sessions
+pub fn decide(strategy: Strategy, game_state: &ApiGameState, budget: Duration, options: SearchOptions, seed: u64) -> ApiDirection {
+    match strategy {
+        Strategy::BestFirst => brain::get_decision_with(game_state, budget, options),
+        Strategy::AlphaBeta => alphabeta::get_decision(game_state, budget, options.weights),
+        Strategy::Mcts => mcts::get_decision(game_state, budget, options.weights, seed),
+    }
+}
+
+//builtins search one at a time. otherwise they'd split the cores between them, and a strategy would be judged by how
+//well it copes with sharing rather than how it plays as a server
+fn search_turn() -> &'static Mutex<()> {
+    static SEARCH_TURN: OnceLock<Mutex<()>> = OnceLock::new();
+    SEARCH_TURN.get_or_init(|| Mutex::new(()))
+}
+
+//our own search, called directly instead of through a server
+pub struct BuiltinPlayer {
+    name: String,
+    strategy: Strategy,
+    options: SearchOptions,
+}
+
+impl BuiltinPlayer {
+    pub fn new(strategy: Strategy, options: SearchOptions) -> BuiltinPlayer {
+        BuiltinPlayer {
+            name: format!("builtin:{:?}", strategy).to_lowercase(),
+            strategy,
+            options,
+        }
+    }
+}
+
+impl Player for BuiltinPlayer {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn start(&self, _game_state: ApiGameState) -> BoxFuture<'_, Result<ApiSnakeConfig, String>> {
+        futures::future::ready(Ok(ApiSnakeConfig {
+            color: String::from(COLOR),
+            head_type: String::from(HEAD),
+            tail_type: String::from(TAIL),
+        })).boxed()
+    }
+
+    //the timeout starts once it's this player's turn to search, since waiting for other builtins isn't its fault
+    fn get_move(&self, game_state: ApiGameState, timeout_ms: u64, seed: u64) -> BoxFuture<'_, Result<ApiMove, String>> {
+        let (strategy, options) = (self.strategy, self.options);
+        let budget = Duration::from_millis(timeout_ms.saturating_sub(BUILTIN_MARGIN_MS));
+        async move {
+            let _turn = search_turn().lock().await;
+            //searching blocks, so it gets its own thread to let other players' moves be awaited meanwhile
+            let search = tokio::task::spawn_blocking(move || decide(strategy, &game_state, budget, options, seed));
+            match timeout(Duration::from_millis(timeout_ms), search).await {
+                Err(_) => Err(format!("Snake @ {} timed out after {} ms", self.name, timeout_ms)),
+                Ok(Err(e)) => Err(format!("Snake @ {} failed to move: {}", self.name, e)),
+                Ok(Ok(decision)) => Ok(ApiMove {decision, shout: None}),
+            }
+        }.boxed()
+    }
+}
+
+//`builtin:<strategy>[:<weights file>]` for an in-process player, otherwise the URL of a snake server
+pub fn parse_player(spec: &str) -> Result<Box<dyn Player>, String> {
+    if let Some(builtin) = spec.strip_prefix("builtin:") {
+        let mut parts = builtin.splitn(2, ':');
+        let strategy = parts.next().unwrap().parse()?;
+        let weights = match parts.next() {
+            Some(path) => Weights::load(path)?,
+            None => Weights::default(),
+        };
+        Ok(Box::new(BuiltinPlayer::new(strategy, SearchOptions {weights, ..SearchOptions::default()})))
+    } else {
+        Ok(Box::new(HttpPlayer::new(spec)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_player() {
+        assert_eq!(parse_player("builtin:alphabeta").unwrap().name(), "builtin:alphabeta");
+        assert_eq!(parse_player("http://localhost:8080").unwrap().name(), "http://localhost:8080");
+        assert!(parse_player("builtin:telepathy").is_err());
+        assert!(parse_player("builtin:mcts:/nonexistent.json").is_err());
+    }
+}
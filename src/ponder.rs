@@ -0,0 +1,133 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+use log::*;
+use crate::api::ApiDirection;
+use crate::brain::{Search, predict_boards};
+use crate::game::Board;
+
+//how many of the likely next boards to search while we wait
+const MAX_PONDER_BOARDS: usize = 8;
+//time each pondered board gets before moving onto the next one
+const PONDER_SLICE_MS: u64 = 5;
+//stop pondering on our own if the next request never comes (e.g. we died)
+const MAX_PONDER_MS: u64 = 5000;
+
+//searches predicted next boards in the background between /move requests
+pub struct Ponder {
+    stop: Arc<AtomicBool>,
+    //round robin passes completed over the predicted boards
+    rounds: Arc<AtomicUsize>,
+    handle: Option<JoinHandle<Vec<Search>>>,
+}
+
+impl Ponder {
+    //`search` is the one we just decided with, and `decision` the move we replied with
+    pub fn start(search: Search, decision: ApiDirection) -> Ponder {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let rounds = Arc::new(AtomicUsize::new(0));
+        let thread_rounds = rounds.clone();
+        let handle = thread::spawn(move || {
+            let start = SystemTime::now();
+            let max_duration = Duration::from_millis(MAX_PONDER_MS);
+            let slice = Duration::from_millis(PONDER_SLICE_MS);
+            //continue from the subtrees we've already explored where possible
+            let mut searches: Vec<Search> = predict_boards(search.root(), decision, MAX_PONDER_BOARDS, &search.options().weights)
+                .into_iter()
+                .map(|board| search.reroot(&board).unwrap_or_else(|| search.sibling(board)))
+                .collect();
+
+            //round robin so each prediction gets a fair share of however long we have
+            let mut exhausted = vec![false; searches.len()];
+            while !thread_stop.load(Ordering::Relaxed) && SystemTime::now().duration_since(start).unwrap() < max_duration {
+                if exhausted.iter().all(|&e| e) {
+                    break;
+                }
+                for (search, exhausted) in searches.iter_mut().zip(exhausted.iter_mut()) {
+                    if !*exhausted {
+                        *exhausted = !search.run(slice, &thread_stop);
+                    }
+                }
+                thread_rounds.fetch_add(1, Ordering::Relaxed);
+            }
+            searches
+        });
+        Ponder {stop, rounds, handle: Some(handle)}
+    }
+
+    //stops pondering and returns the searches made so far
+    pub fn finish(mut self) -> Vec<Search> {
+        self.stop.store(true, Ordering::Relaxed);
+        let searches = self.handle.take().unwrap().join().unwrap_or_else(|_| {
+            error!("Pondering thread panicked");
+            Vec::new()
+        });
+        debug!("Finished pondering: boards={}, rounds={}", searches.len(), self.rounds.load(Ordering::Relaxed));
+        searches
+    }
+
+    //finishes pondering and takes the search rooted at `board`, if we predicted it. food isn't predicted, so any
+    //that spawned is left out of the comparison and the search takes on the real board
+    pub fn take_matching(self, board: &Board) -> Option<Search> {
+        self.finish().into_iter()
+            .find(|search| search.root().same_except_food(board))
+            .map(|search| search.with_root(board.clone()))
+    }
+}
+
+//don't leave the thread running if the session is dropped without finishing
+impl Drop for Ponder {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{ApiGameState, ApiDirection::*};
+    use crate::brain::SearchOptions;
+    use crate::game::Coord;
+
+    #[test]
+    fn test_ponder_hit() {
+        let board = Board::from_api(&ApiGameState::parse_basic("
+        |  |  |  |  |  |
+        |  |Y0|  |  |  |
+        |  |Y1|  |  |  |
+        |  |Y2|  |A0|  |
+        |  |  |  |A1|  |
+        "));
+        let ponder = Ponder::start(Search::new(board.clone(), SearchOptions::default()), Up);
+        while ponder.rounds.load(Ordering::Relaxed) == 0 {
+            thread::yield_now();
+        }
+
+        //food spawned that the prediction didn't have
+        let mut actual = board.clone();
+        actual.advance(None, &[Up, Up]);
+        actual.food.push(Coord::new(0, 0));
+        let search = ponder.take_matching(&actual).unwrap();
+        assert!(search.n_considered() > 0);
+        assert_eq!(search.root(), &actual);
+    }
+
+    #[test]
+    fn test_ponder_miss() {
+        let board = Board::from_api(&ApiGameState::parse_basic("
+        |  |  |  |  |  |
+        |  |Y0|  |  |  |
+        |  |Y1|  |  |  |
+        |  |Y2|  |A0|  |
+        |  |  |  |A1|  |
+        "));
+        let ponder = Ponder::start(Search::new(board.clone(), SearchOptions::default()), Up);
+
+        //we replied Up but the engine used Left
+        let mut actual = board.clone();
+        actual.advance(None, &[Left, Up]);
+        assert!(ponder.take_matching(&actual).is_none());
+    }
+}
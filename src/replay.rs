@@ -0,0 +1,204 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use crate::api::{ApiDirection, ApiGameState, ApiSnakeId};
+use crate::game::{Board, Elimination};
+use crate::util::draw_board;
+
+//one line of a replay file: a game state as snakes are sent it, plus what happened next
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReplayFrame {
+    #[serde(flatten)]
+    pub state: ApiGameState,
+    //moves made from this state. a server only knows its own
+    #[serde(default)]
+    pub moves: Vec<ReplayMove>,
+    //snakes removed by those moves
+    #[serde(default)]
+    pub eliminations: Vec<ReplayElimination>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ReplayMove {
+    pub id: ApiSnakeId,
+    #[serde(rename = "move")]
+    pub decision: ApiDirection,
+    //how long the snake took to respond, in milliseconds
+    pub latency: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ReplayElimination {
+    pub id: ApiSnakeId,
+    pub cause: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub by: Option<ApiSnakeId>,
+    pub turn: u32,
+}
+
+impl ReplayElimination {
+    //`ids` are the snakes on the board the elimination happened on, since `by` is an index into them
+    pub fn new(id: &str, elimination: &Elimination, ids: &[ApiSnakeId]) -> ReplayElimination {
+        ReplayElimination {
+            id: String::from(id),
+            cause: format!("{:?}", elimination.cause),
+            by: elimination.by.and_then(|by| ids.get(by).cloned()),
+            turn: elimination.turn,
+        }
+    }
+}
+
+//empties the file, so a new replay doesn't follow an old one
+pub fn create(path: &str) -> io::Result<()> {
+    File::create(path).map(|_| ())
+}
+
+pub fn append(path: &str, frame: &ReplayFrame) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(frame).unwrap())
+}
+
+//where the server records a game, by its id
+pub fn game_path(dir: &str, game_id: &str) -> String {
+    let file_name = game_id.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' {c} else {'_'}).collect::<String>();
+    Path::new(dir).join(format!("{}.jsonl", file_name)).to_string_lossy().into_owned()
+}
+
+pub fn load(path: &str) -> Result<Vec<ReplayFrame>, String> {
+    let file = fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+    file.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| serde_json::from_str(line).map_err(|e| format!("Invalid frame on line {}: {}", i + 1, e)))
+        .collect()
+}
+
+fn show(frames: &[ReplayFrame], i: usize) {
+    let frame = &frames[i];
+    println!("Frame {}/{}, turn {}", i + 1, frames.len(), frame.state.turn);
+    println!("{}", draw_board(&Board::from_api(&frame.state)));
+    for (snake_index, snake) in Board::api_snakes(&frame.state).enumerate() {
+        let api_move = frame.moves.iter().find(|api_move| api_move.id == snake.id);
+        println!(
+            "  {}: {} ({}), health={}, length={}{}",
+            snake_index,
+            snake.name,
+            snake.id,
+            snake.health,
+            snake.body.len(),
+            api_move.map(|api_move| format!(", move={:?} in {}ms", api_move.decision, api_move.latency)).unwrap_or_default()
+        );
+    }
+    for elimination in frame.eliminations.iter() {
+        println!(
+            "  Eliminated: {} {}{}",
+            elimination.id,
+            elimination.cause,
+            elimination.by.as_ref().map(|by| format!(" by {}", by)).unwrap_or_default()
+        );
+    }
+}
+
+//which frame a command moves to from `i`, or None to quit
+fn step(command: &str, i: usize, n_frames: usize) -> Option<usize> {
+    let last = n_frames.saturating_sub(1);
+    match command.trim() {
+        "" | "n" => Some((i + 1).min(last)),
+        "p" => Some(i.saturating_sub(1)),
+        "f" => Some(0),
+        "l" => Some(last),
+        "q" => None,
+        turn => Some(turn.parse::<usize>().map(|turn| turn.saturating_sub(1).min(last)).unwrap_or(i)),
+    }
+}
+
+//steps through a replay in the terminal, reading commands from stdin
+pub fn run_replay(path: &str) -> Result<(), String> {
+    let frames = load(path)?;
+    if frames.is_empty() {
+        return Err(format!("{} has no frames", path));
+    }
+    let mut i = 0;
+    let stdin = io::stdin();
+    loop {
+        show(&frames, i);
+        println!("[ENTER/n] next, [p] previous, [f] first, [l] last, [number] go to frame, [q] quit");
+        let mut command = String::new();
+        if stdin.lock().read_line(&mut command).unwrap_or(0) == 0 {
+            return Ok(());
+        }
+        match step(&command, i, frames.len()) {
+            Some(next) => i = next,
+            None => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::CauseOfDeath;
+
+    #[test]
+    fn test_frame_round_trip() {
+        let mut state = ApiGameState::parse_basic("
+        |  |Y0|  |A0|
+        |  |Y1|  |A1|
+        ");
+        state.game.id = String::from("game/1");
+        let ids = Board::api_snakes(&state).map(|snake| snake.id.clone()).collect::<Vec<_>>();
+        let n_snakes = state.board.snakes.len();
+        let frame = ReplayFrame {
+            moves: vec![ReplayMove {id: ids[0].clone(), decision: ApiDirection::Left, latency: 12}],
+            eliminations: vec![ReplayElimination::new(&ids[1], &Elimination {cause: CauseOfDeath::HeadToHead, by: Some(0), turn: 1}, &ids)],
+            state,
+        };
+        let path = std::env::temp_dir().join(format!("replay_test_{}.jsonl", std::process::id())).to_string_lossy().into_owned();
+        create(&path).unwrap();
+        append(&path, &frame).unwrap();
+        append(&path, &frame).unwrap();
+        let loaded = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[1].moves, frame.moves);
+        assert_eq!(loaded[1].eliminations[0].by, Some(ids[0].clone()));
+        //frames can still be read as plain game states
+        let line = serde_json::to_string(&frame).unwrap();
+        let state: ApiGameState = serde_json::from_str(&line).unwrap();
+        assert_eq!(state.board.snakes.len(), n_snakes);
+        assert_eq!(game_path("replays", "game/1"), Path::new("replays").join("game_1.jsonl").to_string_lossy());
+    }
+
+    #[test]
+    fn test_finished_game_round_trip() {
+        //the last frame of a draw has no snakes left, not even `you`
+        let mut state = ApiGameState::parse_basic("
+        |  |  |  |  |
+        |  |  |  |  |
+        ");
+        state.board.snakes.clear();
+        state.you = Default::default();
+        let frame = ReplayFrame {moves: Vec::new(), eliminations: Vec::new(), state};
+        let path = std::env::temp_dir().join(format!("replay_test_finished_{}.jsonl", std::process::id())).to_string_lossy().into_owned();
+        create(&path).unwrap();
+        append(&path, &frame).unwrap();
+        let loaded = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(Board::api_snakes(&loaded[0].state).count(), 0);
+        assert!(Board::from_api(&loaded[0].state).snakes.is_empty());
+        show(&loaded, 0);
+    }
+
+    #[test]
+    fn test_step() {
+        assert_eq!(step("\n", 0, 3), Some(1));
+        assert_eq!(step("n", 2, 3), Some(2));
+        assert_eq!(step("p", 0, 3), Some(0));
+        assert_eq!(step("l", 0, 3), Some(2));
+        assert_eq!(step("2", 0, 3), Some(1));
+        assert_eq!(step("q", 1, 3), None);
+    }
+}
@@ -1,15 +1,75 @@
 use std::convert::Infallible;
-use std::net::{SocketAddr, IpAddr};
+use std::sync::{Arc, Mutex};
+use std::net::SocketAddr;
 use std::time::{SystemTime, Duration};
 use log::*;
 use log::Level::Debug;
 use hyper::{Body, Request, Response, Server, Method, StatusCode, body, service::{make_service_fn, service_fn}};
-use crate::api::{ApiSnakeConfig, ApiMove};
-use crate::brain::get_decision;
+use crate::api::{ApiDirection, ApiGameState, ApiSnakeConfig, ApiSnakeInfo, ApiMove, API_VERSION};
+use std::sync::atomic::AtomicBool;
+use crate::brain::{Search, Strategy, SearchOptions};
+use crate::{alphabeta, mcts};
+use crate::game::Board;
+use crate::ponder::Ponder;
+use crate::session::{GameSession, SessionRegistry};
+use crate::replay::{self, ReplayFrame, ReplayMove};
 
-pub async fn start_server(ip: IpAddr, port: u16, budget: u64) {
-    let addr = SocketAddr::new(ip, port);
+const AUTHOR: &str = "csauve";
+pub const COLOR: &str = "#800080";
+pub const HEAD: &str = "bendr";
+pub const TAIL: &str = "round-bum";
+//sessions which haven't seen a request in this long are assumed to have missed their /end
+const SESSION_TIMEOUT_MS: u64 = 60_000;
+
+fn bad_request(message: String) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .header("Content-Type", "text/plain")
+        .body(Body::from(message))
+        .unwrap()
+}
+
+//searching blocks, so this is run off the executor. the session is only locked around the search, not during it
+fn search_move(session: &Mutex<GameSession>, game_state: &ApiGameState, budget: Duration, ponder: bool, strategy: Strategy, options: SearchOptions) -> (ApiDirection, Duration, Duration) {
+    let board = Board::from_api(game_state);
+    let (move_budget, previous) = {
+        let mut session = session.lock().unwrap();
+        session.observe(game_state);
+        let previous = if strategy == Strategy::BestFirst {session.take_search(&board)} else {None};
+        (session.budget(game_state, budget), previous)
+    };
+
+    let start = SystemTime::now();
+    let (decision, search) = match strategy {
+        Strategy::BestFirst => {
+            let mut search = previous
+                .inspect(|search| info!("Resuming search: n_nodes={}, n_considered={}", search.n_nodes(), search.n_considered()))
+                .unwrap_or_else(|| Search::new(board, options));
+            search.run(move_budget, &AtomicBool::new(false));
+            search.log_summary();
+            (search.decision(), Some(search))
+        },
+        Strategy::AlphaBeta => (alphabeta::get_decision(game_state, move_budget, options.weights), None),
+        Strategy::Mcts => (mcts::get_decision(game_state, move_budget, options.weights, rand::random()), None),
+    };
+    let elapsed = SystemTime::now().duration_since(start).unwrap();
+
+    let mut session = session.lock().unwrap();
+    session.timings.record(elapsed);
+    if let Some(search) = search {
+        if ponder {
+            session.ponder = Some(Ponder::start(search, decision));
+        } else {
+            session.last_search = Some(search);
+        }
+    }
+    (decision, move_budget, elapsed)
+}
+
+//`replay_dir` gets a replay file per game, built from the requests we receive
+pub async fn start_server(addr: SocketAddr, budget: u64, margin: u64, ponder: bool, strategy: Strategy, options: SearchOptions, replay_dir: Option<String>) {
     let budget = Duration::from_millis(budget);
+    let margin = Duration::from_millis(margin);
     println!("
     ┌────────────────────────────────┐
     │ ╖                              │
@@ -21,24 +81,61 @@ pub async fn start_server(ip: IpAddr, port: u16, budget: u64) {
     &addr
     );
 
-    let server = Server::bind(&addr).serve(make_service_fn(|_socket|
+    let sessions = Arc::new(SessionRegistry::new(Duration::from_millis(SESSION_TIMEOUT_MS), margin));
+    //evict on a timer too, otherwise stale sessions (and their pondering) linger until the next request
+    let evicting = sessions.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(SESSION_TIMEOUT_MS));
+        loop {
+            interval.tick().await;
+            evicting.evict_stale();
+        }
+    });
+
+    let server = Server::bind(&addr).serve(make_service_fn(move |_socket| {
+        let sessions = sessions.clone();
+        let replay_dir = replay_dir.clone();
         async move {
-            Ok::<_, Infallible>(service_fn(move |req: Request<Body>|
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let sessions = sessions.clone();
+                let replay_dir = replay_dir.clone();
                 async move {
                     Ok::<_, Infallible>(match (req.method(), req.uri().path()) {
                         (&Method::GET, "/") => {
+                            let json = serde_json::to_string(&ApiSnakeInfo {
+                                apiversion: String::from(API_VERSION),
+                                author: String::from(AUTHOR),
+                                color: String::from(COLOR),
+                                head: String::from(HEAD),
+                                tail: String::from(TAIL),
+                                version: String::from(env!("CARGO_PKG_VERSION")),
+                            }).unwrap();
                             debug!("Handled /");
-                            Response::new(Body::from("What'sssss up?"))
+                            Response::builder()
+                                .header("Content-Type", "application/json")
+                                .body(Body::from(json))
+                                .unwrap()
                         },
                         (&Method::POST, "/ping") => {
                             debug!("Handled /ping");
                             Response::new(Body::empty())
                         },
                         (&Method::POST, "/start") => {
+                            let bytes = match body::to_bytes(req.into_body()).await {
+                                Ok(bytes) => bytes,
+                                Err(e) => return Ok(bad_request(format!("The request body could not be read: {}", e))),
+                            };
+                            let game_state = match serde_json::from_slice::<ApiGameState>(&bytes) {
+                                Ok(game_state) => game_state,
+                                Err(_) => return Ok(bad_request(String::from("The request body could not be parsed as valid JSON"))),
+                            };
+                            sessions.start(&game_state.game.id).lock().unwrap().observe(&game_state);
+                            info!("Started session: game={}, sessions={}", &game_state.game.id, sessions.len());
+                            //v1 engines ignore this body, but 2020 engines read the snake's appearance from it
                             let json = serde_json::to_string(&ApiSnakeConfig {
-                                color: String::from("#800080"),
-                                head_type: String::from("bendr"),
-                                tail_type: String::from("round-bum"),
+                                color: String::from(COLOR),
+                                head_type: String::from(HEAD),
+                                tail_type: String::from(TAIL),
                             }).unwrap();
                             debug!("Handled /start");
                             Response::builder()
@@ -47,21 +144,51 @@ pub async fn start_server(ip: IpAddr, port: u16, budget: u64) {
                                 .unwrap()
                         },
                         (&Method::POST, "/move") => {
-                            let bytes = body::to_bytes(req.into_body()).await.unwrap();
-                            match serde_json::from_slice(&bytes) {
+                            let bytes = match body::to_bytes(req.into_body()).await {
+                                Ok(bytes) => bytes,
+                                Err(e) => return Ok(bad_request(format!("The request body could not be read: {}", e))),
+                            };
+                            match serde_json::from_slice::<ApiGameState>(&bytes) {
                                 Ok(game_state) => {
                                     if log_enabled!(Debug) {
                                         debug!("Parsed request body: {}", serde_json::to_string_pretty(&game_state).unwrap());
                                     }
 
-                                    let start = SystemTime::now();
-                                    let decision = get_decision(&game_state, budget);
-                                    let json = serde_json::to_string(&ApiMove {decision}).unwrap();
-                                    let duration = SystemTime::now().duration_since(start).unwrap().as_millis();
+                                    let session = sessions.get_or_start(&game_state.game.id);
+                                    let search_state = game_state.clone();
+                                    let searched = tokio::task::spawn_blocking(move || {
+                                        search_move(&session, &search_state, budget, ponder, strategy, options)
+                                    }).await;
+                                    let (decision, move_budget, elapsed) = match searched {
+                                        Ok(searched) => searched,
+                                        Err(e) => {
+                                            error!("Search failed: game={}, turn={}: {}", &game_state.game.id, &game_state.turn, e);
+                                            return Ok(Response::builder()
+                                                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                                                .body(Body::empty())
+                                                .unwrap());
+                                        }
+                                    };
+                                    let json = serde_json::to_string(&ApiMove {decision, shout: None}).unwrap();
+                                    let duration = elapsed.as_millis();
+                                    if let Some(dir) = replay_dir.as_ref() {
+                                        let path = replay::game_path(dir, &game_state.game.id);
+                                        let frame = ReplayFrame {
+                                            moves: vec![ReplayMove {id: game_state.you.id.clone(), decision, latency: duration as u64}],
+                                            eliminations: Vec::new(),
+                                            state: game_state.clone(),
+                                        };
+                                        //file writes block, so they're kept off the executor too
+                                        let appended = tokio::task::spawn_blocking(move || replay::append(&path, &frame).map_err(|e| (path, e))).await;
+                                        if let Ok(Err((path, e))) = appended {
+                                            error!("Couldn't record move to replay {}: {}", path, e);
+                                        }
+                                    }
                                     info!(
-                                        "Handled /move: game={}, turn={}, duration={}ms, move={:?}",
+                                        "Handled /move: game={}, turn={}, budget={}ms, duration={}ms, move={:?}",
                                         &game_state.game.id,
                                         &game_state.turn,
+                                        move_budget.as_millis(),
                                         duration,
                                         &decision
                                     );
@@ -70,16 +197,33 @@ pub async fn start_server(ip: IpAddr, port: u16, budget: u64) {
                                         .body(Body::from(json))
                                         .unwrap()
                                 },
-                                Err(_) => {
-                                    Response::builder()
-                                        .status(StatusCode::BAD_REQUEST)
-                                        .header("Content-Type", "text/plain")
-                                        .body(Body::from("The request body could not be parsed as valid JSON"))
-                                        .unwrap()
-                                }
+                                Err(_) => bad_request(String::from("The request body could not be parsed as valid JSON")),
                             }
                         },
                         (&Method::POST, "/end") => {
+                            let bytes = match body::to_bytes(req.into_body()).await {
+                                Ok(bytes) => bytes,
+                                Err(e) => return Ok(bad_request(format!("The request body could not be read: {}", e))),
+                            };
+                            let game_state = match serde_json::from_slice::<ApiGameState>(&bytes) {
+                                Ok(game_state) => game_state,
+                                Err(_) => return Ok(bad_request(String::from("The request body could not be parsed as valid JSON"))),
+                            };
+                            if let Some(session) = sessions.end(&game_state.game.id) {
+                                let mut session = session.lock().unwrap();
+                                if let Some(ponder) = session.ponder.take() {
+                                    ponder.finish();
+                                }
+                                info!(
+                                    "Ended session: game={}, turns={}, mean={}ms, max={}ms, late={}, duration={}s",
+                                    &session.game_id,
+                                    session.timings.n_moves,
+                                    session.timings.mean().as_millis(),
+                                    session.timings.max.as_millis(),
+                                    session.latency.n_late,
+                                    session.started.elapsed().as_secs()
+                                );
+                            }
                             debug!("Handled /end");
                             Response::new(Body::empty())
                         },
@@ -91,9 +235,9 @@ pub async fn start_server(ip: IpAddr, port: u16, budget: u64) {
                         }
                     })
                 }
-            ))
+            }))
         }
-    ));
+    }));
 
     if let Err(e) = server.await {
         eprintln!("Server error: {}", e);
@@ -0,0 +1,316 @@
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+use log::*;
+use crate::api::{ApiGameId, ApiGameState, ApiSnakeId, ApiDirection};
+use crate::brain::Search;
+use crate::game::{Board, Coord};
+use crate::ponder::Ponder;
+
+//never think for less than this, even if the network looks hopeless
+const MIN_BUDGET_MS: u64 = 10;
+//how much the margin grows each time we respond late
+const LATE_MARGIN_FACTOR: u32 = 2;
+//the margin grows from at least this, so a margin of 0 can still recover from being late
+const MIN_LATE_MARGIN_MS: u64 = 10;
+//fraction of the margin above the configured one given back after each on-time response
+const MARGIN_DECAY: f64 = 0.1;
+//weight of a new overhead sample when it's lower than the estimate; spikes are adopted immediately
+const OVERHEAD_DECAY: f64 = 0.2;
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct TimingStats {
+    pub n_moves: u32,
+    pub total: Duration,
+    pub max: Duration,
+    pub last: Option<Duration>,
+}
+
+impl TimingStats {
+    pub fn record(&mut self, duration: Duration) {
+        self.n_moves += 1;
+        self.total += duration;
+        self.last = Some(duration);
+        if duration > self.max {
+            self.max = duration;
+        }
+    }
+
+    pub fn mean(&self) -> Duration {
+        if self.n_moves == 0 {
+            Duration::from_millis(0)
+        } else {
+            self.total / self.n_moves
+        }
+    }
+}
+
+//estimates how much of the engine's timeout is lost outside of our own thinking time
+#[derive(Clone, Copy, Debug)]
+pub struct LatencyModel {
+    pub overhead: Duration,
+    pub margin: Duration,
+    //the margin we were configured with, which `margin` decays back to
+    pub base_margin: Duration,
+    pub n_late: u32,
+}
+
+impl LatencyModel {
+    pub fn new(margin: Duration) -> LatencyModel {
+        LatencyModel {
+            overhead: Duration::from_millis(0),
+            margin,
+            base_margin: margin,
+            n_late: 0,
+        }
+    }
+
+    //`latency` is the engine's measured round trip for a response which took us `elapsed` to compute
+    pub fn observe(&mut self, latency: Duration, elapsed: Duration, timeout: Option<Duration>) {
+        let sample = latency.checked_sub(elapsed).unwrap_or_default();
+        self.overhead = if sample > self.overhead {
+            sample
+        } else {
+            self.overhead.mul_f64(1.0 - OVERHEAD_DECAY) + sample.mul_f64(OVERHEAD_DECAY)
+        };
+        if let Some(timeout) = timeout {
+            if latency >= timeout {
+                self.n_late += 1;
+                self.margin = self.margin.max(Duration::from_millis(MIN_LATE_MARGIN_MS)) * LATE_MARGIN_FACTOR;
+                warn!("Response was late ({}ms >= {}ms); raising margin to {}ms", latency.as_millis(), timeout.as_millis(), self.margin.as_millis());
+            } else if self.margin > self.base_margin {
+                let excess = self.margin - self.base_margin;
+                self.margin = self.base_margin + excess.mul_f64(1.0 - MARGIN_DECAY);
+            }
+        }
+    }
+
+    pub fn budget(&self, timeout: Duration) -> Duration {
+        let min_budget = Duration::from_millis(MIN_BUDGET_MS);
+        timeout.checked_sub(self.overhead + self.margin)
+            .filter(|&budget| budget > min_budget)
+            .unwrap_or(min_budget)
+    }
+}
+
+//everything we know about a game in progress, kept between requests
+pub struct GameSession {
+    pub game_id: ApiGameId,
+    pub started: Instant,
+    pub last_seen: Instant,
+    pub last_state: Option<ApiGameState>,
+    //moves each other snake has been observed to make, in turn order
+    pub opponent_moves: HashMap<ApiSnakeId, Vec<ApiDirection>>,
+    pub timings: TimingStats,
+    pub latency: LatencyModel,
+    pub ponder: Option<Ponder>,
+    //the previous turn's search, kept to be re-rooted if we aren't pondering
+    pub last_search: Option<Search>,
+}
+
+impl GameSession {
+    pub fn new(game_id: &str, margin: Duration) -> GameSession {
+        let now = Instant::now();
+        GameSession {
+            game_id: ApiGameId::from(game_id),
+            started: now,
+            last_seen: now,
+            last_state: None,
+            opponent_moves: HashMap::new(),
+            timings: TimingStats::default(),
+            latency: LatencyModel::new(margin),
+            ponder: None,
+            last_search: None,
+        }
+    }
+
+    //time we can spend thinking about this turn; falls back to the default if the engine has no timeout
+    pub fn budget(&self, game_state: &ApiGameState, default: Duration) -> Duration {
+        game_state.game.timeout
+            .map(|timeout| self.latency.budget(Duration::from_millis(timeout as u64)))
+            .unwrap_or(default)
+    }
+
+    //infers the moves opponents made since the last state we saw by comparing head positions
+    pub fn observe(&mut self, game_state: &ApiGameState) {
+        self.last_seen = Instant::now();
+        if let Some(last_state) = self.last_state.as_ref() {
+            if game_state.turn == last_state.turn + 1 {
+                //the engine reports latency of our previous response, which we know our share of
+                if let (Some(latency), Some(elapsed)) = (game_state.you.latency_ms(), self.timings.last) {
+                    let timeout = game_state.game.timeout.map(|timeout| Duration::from_millis(timeout as u64));
+                    self.latency.observe(Duration::from_millis(latency), elapsed, timeout);
+                }
+                for snake in game_state.board.snakes.iter().filter(|s| s.id != game_state.you.id) {
+                    let prev_head = last_state.board.snakes.iter()
+                        .find(|prev| prev.id == snake.id)
+                        .and_then(|prev| prev.body.first());
+                    if let (Some(prev_head), Some(head)) = (prev_head, snake.body.first()) {
+                        //heads on opposite edges moved across them on a wrapped board
+                        if let Ok(dir) = ApiDirection::try_from((Coord::from(head) - Coord::from(prev_head)).unwrap_neighbour()) {
+                            self.opponent_moves.entry(snake.id.clone()).or_default().push(dir);
+                        }
+                    }
+                }
+            }
+        }
+        self.last_state = Some(game_state.clone());
+    }
+
+    //finds previous work rooted at `board`, whether pondered or left over from the last turn
+    pub fn take_search(&mut self, board: &Board) -> Option<Search> {
+        if let Some(ponder) = self.ponder.take() {
+            self.last_search = None;
+            return ponder.take_matching(board);
+        }
+        self.last_search.take().and_then(|search| search.reroot(board))
+    }
+}
+
+//sessions are keyed by game ID and evicted if they go quiet without an /end
+pub struct SessionRegistry {
+    sessions: Mutex<HashMap<ApiGameId, Arc<Mutex<GameSession>>>>,
+    timeout: Duration,
+    margin: Duration,
+}
+
+impl SessionRegistry {
+    pub fn new(timeout: Duration, margin: Duration) -> SessionRegistry {
+        SessionRegistry {
+            sessions: Mutex::new(HashMap::new()),
+            timeout,
+            margin,
+        }
+    }
+
+    //begins a new session, replacing any previous one for the same game
+    pub fn start(&self, game_id: &str) -> Arc<Mutex<GameSession>> {
+        self.evict_stale();
+        let session = Arc::new(Mutex::new(GameSession::new(game_id, self.margin)));
+        self.sessions.lock().unwrap().insert(ApiGameId::from(game_id), session.clone());
+        session
+    }
+
+    //the session may be missing if we restarted mid-game or missed /start, so create one if needed
+    pub fn get_or_start(&self, game_id: &str) -> Arc<Mutex<GameSession>> {
+        self.evict_stale();
+        let existing = self.sessions.lock().unwrap().get(game_id).cloned();
+        existing.unwrap_or_else(|| {
+            debug!("No session for game {}; starting one", game_id);
+            self.start(game_id)
+        })
+    }
+
+    pub fn end(&self, game_id: &str) -> Option<Arc<Mutex<GameSession>>> {
+        self.evict_stale();
+        self.sessions.lock().unwrap().remove(game_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    pub fn evict_stale(&self) {
+        let timeout = self.timeout;
+        self.sessions.lock().unwrap().retain(|game_id, session| {
+            //a session locked by an in-flight request is clearly not stale
+            let stale = session.try_lock()
+                .map(|session| session.last_seen.elapsed() > timeout)
+                .unwrap_or(false);
+            if stale {
+                info!("Evicting stale session for game {}", game_id);
+            }
+            !stale
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use crate::api::ApiDirection::*;
+
+    #[test]
+    fn test_observe_opponent_moves() {
+        let mut session = GameSession::new("123", Duration::from_millis(50));
+        let mut game_state = ApiGameState::parse_basic("
+        |  |  |  |
+        |Y0|  |A0|
+        |Y1|  |A1|
+        ");
+        session.observe(&game_state);
+        assert!(session.opponent_moves.is_empty());
+
+        let next_state = ApiGameState::parse_basic("
+        |Y0|  |  |
+        |Y1|A0|A1|
+        |  |  |  |
+        ");
+        game_state.turn = 1;
+        game_state.board = next_state.board;
+        game_state.you = next_state.you;
+        session.observe(&game_state);
+        assert_eq!(session.opponent_moves.get("id_A").unwrap(), &[Left]);
+        assert!(!session.opponent_moves.contains_key("id_Y"));
+    }
+
+    #[test]
+    fn test_evict_stale() {
+        let registry = SessionRegistry::new(Duration::from_millis(10), Duration::from_millis(50));
+        registry.start("abc");
+        assert_eq!(registry.len(), 1);
+        thread::sleep(Duration::from_millis(20));
+        registry.get_or_start("def");
+        assert_eq!(registry.len(), 1);
+        assert!(registry.end("abc").is_none());
+        assert!(registry.end("def").is_some());
+    }
+
+    #[test]
+    fn test_latency_budget() {
+        let ms = Duration::from_millis;
+        let mut latency = LatencyModel::new(ms(50));
+        assert_eq!(latency.budget(ms(500)), ms(450));
+
+        //100ms lost to the network
+        latency.observe(ms(400), ms(300), Some(ms(500)));
+        assert_eq!(latency.budget(ms(500)), ms(350));
+
+        //overhead estimate recovers slowly from a spike
+        latency.observe(ms(300), ms(300), Some(ms(500)));
+        assert_eq!(latency.budget(ms(500)), ms(370));
+
+        //responding late doubles the margin
+        latency.observe(ms(600), ms(300), Some(ms(500)));
+        assert_eq!(latency.n_late, 1);
+        assert_eq!(latency.budget(ms(500)), ms(100));
+
+        assert_eq!(latency.budget(ms(100)), ms(MIN_BUDGET_MS));
+    }
+
+    #[test]
+    fn test_latency_margin_recovers() {
+        let ms = Duration::from_millis;
+        //a margin of 0 still grows when we're late
+        let mut latency = LatencyModel::new(ms(0));
+        latency.observe(ms(500), ms(450), Some(ms(500)));
+        assert_eq!(latency.margin, ms(2 * MIN_LATE_MARGIN_MS));
+
+        //and shrinks back towards 0 while we're on time, without going below it
+        latency.observe(ms(400), ms(350), Some(ms(500)));
+        assert_eq!(latency.margin, ms(18));
+        for _ in 0..100 {
+            latency.observe(ms(400), ms(350), Some(ms(500)));
+        }
+        assert!(latency.margin < ms(1));
+
+        let mut latency = LatencyModel::new(ms(50));
+        latency.observe(ms(500), ms(450), Some(ms(500)));
+        for _ in 0..100 {
+            latency.observe(ms(400), ms(350), Some(ms(500)));
+        }
+        assert!(latency.margin >= ms(50) && latency.margin < ms(51));
+    }
+}
@@ -0,0 +1,155 @@
+use std::fs;
+use log::*;
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use crate::alphabeta::get_decision_at_depth;
+use crate::brain::Score;
+use crate::game::{Board, GameMode, Settings, UnitAbs};
+use crate::heuristic::{Weights, N_COMPONENTS};
+use crate::host::{build_api_game_state, play_turn};
+
+//games are called a draw after this many turns, in case neither variant can finish the other
+const MAX_TURNS: u32 = 500;
+//weights are kept between 0 and this while tuning
+const MAX_WEIGHT: Score = 4.0;
+//SPSA gain sequences: steps of STEP_SIZE / (k + 1 + STABILITY) ^ 0.602, perturbations of PERTURBATION / (k + 1) ^ 0.101
+const STEP_SIZE: Score = 0.5;
+const STABILITY: Score = 5.0;
+const PERTURBATION: Score = 0.25;
+const STEP_DECAY: Score = 0.602;
+const PERTURBATION_DECAY: Score = 0.101;
+
+pub struct TuneConfig {
+    pub iterations: usize,
+    //games per iteration, played in pairs from the same start with sides swapped
+    pub games: usize,
+    //turns both variants search ahead with alpha-beta. a fixed depth rather than a time budget means neither the
+    //machine's speed nor its load changes the moves, so a seed always replays the same games
+    pub depth: usize,
+    pub width: UnitAbs,
+    pub height: UnitAbs,
+    pub mode: GameMode,
+    pub settings: Settings,
+    pub seed: u64,
+    //profile the current weights are written to after every iteration
+    pub out: String,
+}
+
+//a headless game between two variants from the same seeded start and food, scored for the first: 1 for a win, 0 for a loss, 0.5 for a draw
+pub fn play_game(config: &TuneConfig, first: Weights, second: Weights, seed: u64) -> Score {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut board = Board::init(config.width, config.height, 2, config.mode, config.settings, &mut rng).unwrap();
+    //which variant each snake still on the board is playing, by original index
+    let mut ids = vec![0, 1];
+    let names = ["first", "second"];
+    while !board.game_over() && board.turn() < MAX_TURNS {
+        let identities = ids.iter().map(|&id| (names[id], names[id])).collect::<Vec<_>>();
+        let snake_moves = ids.iter().enumerate().map(|(snake_index, &id)| {
+            let game_state = build_api_game_state(&board, snake_index, "tune", 0, &identities);
+            let weights = if id == 0 {first} else {second};
+            get_decision_at_depth(&Board::from_api(&game_state), config.depth, weights)
+        }).collect::<Vec<_>>();
+        let eliminations = play_turn(&mut board, &snake_moves, &mut rng);
+        ids = ids.into_iter().enumerate()
+            .filter(|(snake_index, _)| !eliminations.contains_key(snake_index))
+            .map(|(_, id)| id)
+            .collect();
+    }
+    match ids.as_slice() {
+        [0] => 1.0,
+        [1] => 0.0,
+        _ => 0.5,
+    }
+}
+
+fn perturb(weights: Weights, delta: &[Score; N_COMPONENTS], c_k: Score) -> Weights {
+    let mut perturbed = weights;
+    for (weight, d) in perturbed.0.iter_mut().zip(delta.iter()) {
+        *weight = (*weight + c_k * d).clamp(0.0, MAX_WEIGHT);
+    }
+    perturbed
+}
+
+//moves each weight along its estimated gradient, given how much better the +delta variant did than the -delta one
+fn spsa_step(weights: Weights, delta: &[Score; N_COMPONENTS], a_k: Score, c_k: Score, advantage: Score) -> Weights {
+    let mut stepped = weights;
+    for (weight, d) in stepped.0.iter_mut().zip(delta.iter()) {
+        *weight = (*weight + a_k * advantage / (2.0 * c_k * d)).clamp(0.0, MAX_WEIGHT);
+    }
+    stepped
+}
+
+//simultaneous perturbation stochastic approximation: each iteration plays a randomly nudged pair of variants against
+//each other, then moves every weight at once towards whichever side won more
+pub fn tune(config: &TuneConfig, start: Weights) -> Weights {
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let n_pairs = config.games.div_ceil(2).max(1);
+    let mut weights = start;
+    for k in 0..config.iterations {
+        let a_k = STEP_SIZE / (k as Score + 1.0 + STABILITY).powf(STEP_DECAY);
+        let c_k = PERTURBATION / (k as Score + 1.0).powf(PERTURBATION_DECAY);
+        let mut delta = [0.0; N_COMPONENTS];
+        for d in delta.iter_mut() {
+            *d = if rng.gen() {1.0} else {-1.0};
+        }
+        let plus = perturb(weights, &delta, c_k);
+        let minus = perturb(weights, &delta, -c_k);
+
+        let seeds = (0..n_pairs).map(|_| rng.gen()).collect::<Vec<u64>>();
+        let plus_score: Score = seeds.iter().map(|&seed| {
+            play_game(config, plus, minus, seed) + 1.0 - play_game(config, minus, plus, seed)
+        }).sum();
+        let win_rate = plus_score / (2 * n_pairs) as Score;
+
+        weights = spsa_step(weights, &delta, a_k, c_k, 2.0 * win_rate - 1.0);
+        info!("Iteration {}/{}: win_rate={:.3}, weights={:?}", k + 1, config.iterations, win_rate, weights.0);
+        if let Err(e) = fs::write(&config.out, weights.to_json()) {
+            error!("Couldn't write weights to {}: {}", config.out, e);
+        }
+    }
+    weights
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> TuneConfig {
+        TuneConfig {
+            iterations: 1,
+            games: 2,
+            depth: 2,
+            width: 7,
+            height: 7,
+            mode: GameMode::Standard,
+            settings: Settings::default(),
+            seed: 1,
+            out: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_spsa_step() {
+        let weights = Weights([1.0; N_COMPONENTS]);
+        let mut delta = [1.0; N_COMPONENTS];
+        delta[0] = -1.0;
+        //the +delta variant won every game, so weights move the way it was nudged
+        let stepped = spsa_step(weights, &delta, 0.1, 0.5, 1.0);
+        assert_eq!(stepped.0[0], 0.9);
+        assert_eq!(stepped.0[1], 1.1);
+        //and never go negative
+        assert_eq!(spsa_step(weights, &delta, 10.0, 0.5, 1.0).0[0], 0.0);
+        assert_eq!(perturb(weights, &delta, 0.25).0[0], 0.75);
+    }
+
+    #[test]
+    fn test_play_game() {
+        //lopsided weights, so the result depends on which side is which
+        let mut greedy = Weights::default();
+        greedy.0[0] = MAX_WEIGHT;
+        for seed in 0..3 {
+            let score = play_game(&config(), Weights::default(), greedy, seed);
+            assert_eq!(play_game(&config(), Weights::default(), greedy, seed), score);
+        }
+    }
+}
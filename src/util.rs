@@ -58,6 +58,10 @@ pub fn draw_board(board: &Board) -> String {
         }).take(w).collect::<Vec<_>>()
     }).take(h).collect::<Vec<_>>();
 
+    for &Coord {x, y} in board.hazards.iter() {
+        grid[y as usize][x as usize] = Colour::Red.dimmed().paint("~").to_string();
+    }
+
     for &Coord {x, y} in board.food.iter() {
         grid[y as usize][x as usize] = String::from("*");
     }